@@ -0,0 +1,368 @@
+//! This module handles the backup archives, holding the images of all the
+//! memories of a radio together with a manifest describing their origin
+
+/*!
+A backup archive is a plain tar file (ustar), so that it can be inspected
+with standard tools. The first entry is `manifest.txt`, followed by one
+`memN.bin` image for each memory listed in it:
+
+```text
+format = 1
+rtxlink = 0.1.0
+created = 2024-05-04T10:21:33+00:00
+info = MD-UV3x0 fw=v0.3.6
+
+[memory 0]
+name = Internal Flash
+size = 1048576
+flags = 0x62
+sha256 = 5f70bf18a086007016e948b04aed3b82103a36bea41755b6cddfaf10ace3c6ef
+file = mem0.bin
+```
+
+`info` is the reply of the radio to CAT INFO, `created` the host time in
+UTC. Unknown keys are ignored so that later versions can add fields.
+*/
+
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+
+use crate::checksum::Algorithm;
+use crate::fmp::{MemFlags, MemInfo};
+use crate::hex;
+use crate::info::RadioInfo;
+
+/// Version of the manifest format written by this version of rtxlink
+pub const FORMAT: u32 = 1;
+
+const MANIFEST_NAME: &str = "manifest.txt";
+const BLOCK_SIZE: usize = 512;
+
+/// Description of a memory image stored in an archive
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MemoryEntry {
+    pub index: usize,       // Index of the memory on the radio
+    pub info: MemInfo,
+    pub sha256: Vec<u8>,    // Digest of the image
+    pub file: String,       // Name of the image in the archive
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Manifest {
+    pub format: u32,
+    pub version: String,    // Version of rtxlink which wrote the archive
+    pub created: DateTime<Utc>,
+    pub radio: RadioInfo,
+    pub memories: Vec<MemoryEntry>,
+}
+
+/// Content of an archive, with the images in the order of the manifest
+pub struct Backup {
+    pub manifest: Manifest,
+    pub images: Vec<Vec<u8>>,
+}
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.into())
+}
+
+/// Escape a value so that it fits in a single line
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+impl Manifest {
+    /// Manifest of a new backup of the given radio, created now
+    pub fn new(radio: RadioInfo) -> Manifest {
+        Manifest {
+            format: FORMAT,
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            created: Utc::now(),
+            radio,
+            memories: Vec::new(),
+        }
+    }
+
+    /// Add the image of a memory, returning the name of its archive entry
+    pub fn add(&mut self, index: usize, info: &MemInfo, image: &[u8]) -> String {
+        let file = format!("mem{index}.bin");
+        self.memories.push(MemoryEntry {
+            index,
+            info: info.clone(),
+            sha256: Algorithm::SHA256.digest(image),
+            file: file.clone(),
+        });
+        file
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest> {
+        let mut manifest = Manifest::new(RadioInfo::parse(""));
+        let (mut format, mut created) = (None, None);
+        let mut memory: Option<(usize, Vec<(String, String)>)> = None;
+        let mut sections = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let index = header.strip_prefix("memory ")
+                                  .and_then(|i| i.trim().parse::<usize>().ok())
+                                  .ok_or(invalid(format!("manifest line {}: invalid section {line}", n + 1)))?;
+                sections.extend(memory.replace((index, Vec::new())));
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                                   .ok_or(invalid(format!("manifest line {}: expected key = value", n + 1)))?;
+            let (key, value) = (key.trim().to_string(), unescape(value.trim()));
+            match &mut memory {
+                Some((_, fields)) => fields.push((key, value)),
+                None => match key.as_str() {
+                    "format" => format = value.parse::<u32>().ok(),
+                    "rtxlink" => manifest.version = value,
+                    "created" => created = Some(DateTime::parse_from_rfc3339(&value)
+                                                    .map_err(|e| invalid(format!("manifest creation time: {e}")))?
+                                                    .with_timezone(&Utc)),
+                    "info" => manifest.radio = RadioInfo::parse(&value),
+                    _ => (),
+                },
+            }
+        }
+        sections.extend(memory);
+        manifest.format = format.ok_or(invalid("manifest without format"))?;
+        if manifest.format > FORMAT {
+            return Err(invalid(format!("manifest format {} is newer than the supported {FORMAT}", manifest.format)));
+        }
+        manifest.created = created.ok_or(invalid("manifest without creation time"))?;
+        for (index, fields) in sections {
+            manifest.memories.push(MemoryEntry::parse(index, &fields)?);
+        }
+        Ok(manifest)
+    }
+}
+
+impl MemoryEntry {
+    fn parse(index: usize, fields: &[(String, String)]) -> Result<MemoryEntry> {
+        let field = |key: &str| {
+            fields.iter()
+                  .find(|(k, _)| k == key)
+                  .map(|(_, v)| v.as_str())
+                  .ok_or(invalid(format!("memory {index} without {key} in manifest")))
+        };
+        let number = |key: &str| {
+            let value = field(key)?;
+            let parsed = match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => value.parse::<u32>(),
+            };
+            parsed.map_err(|_| invalid(format!("memory {index}: invalid {key} {value}")))
+        };
+        let flags = u8::try_from(number("flags")?).map_err(|_| invalid(format!("memory {index}: invalid flags")))?;
+        let sha256 = hex::parse(field("sha256")?).map_err(|e| invalid(format!("memory {index}: {e}")))?;
+        if sha256.len() != Algorithm::SHA256.digest_size() {
+            return Err(invalid(format!("memory {index}: SHA-256 digest of {} bytes", sha256.len())));
+        }
+        Ok(MemoryEntry {
+            index,
+            info: MemInfo { size: number("size")?, flags: MemFlags(flags), name: String::from(field("name")?) },
+            sha256,
+            file: String::from(field("file")?),
+        })
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "format = {}", self.format)?;
+        writeln!(f, "rtxlink = {}", self.version)?;
+        writeln!(f, "created = {}", self.created.to_rfc3339())?;
+        writeln!(f, "info = {}", escape(&self.radio.raw))?;
+        for mem in &self.memories {
+            writeln!(f)?;
+            writeln!(f, "[memory {}]", mem.index)?;
+            writeln!(f, "name = {}", escape(&mem.info.name))?;
+            writeln!(f, "size = {}", mem.info.size)?;
+            writeln!(f, "flags = {:#04x}", mem.info.flags.0)?;
+            writeln!(f, "sha256 = {}", hex::encode(&mem.sha256))?;
+            writeln!(f, "file = {}", mem.file)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write an octal number in a tar header field, NUL terminated
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+fn get_octal(field: &[u8]) -> Result<u64> {
+    let text: String = field.iter()
+                            .take_while(|&&b| b != 0)
+                            .map(|&b| b as char)
+                            .collect();
+    u64::from_str_radix(text.trim(), 8).map_err(|_| invalid("invalid number in tar header"))
+}
+
+fn header_checksum(header: &[u8]) -> u64 {
+    // The checksum field itself counts as spaces
+    header.iter()
+          .enumerate()
+          .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+          .sum()
+}
+
+/// Append a file to a tar archive
+fn write_entry(out: &mut impl Write, name: &str, data: &[u8], mtime: i64) -> Result<()> {
+    if name.len() >= 100 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("archive entry name too long: {name}")));
+    }
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    put_octal(&mut header[100..108], 0o644);
+    put_octal(&mut header[108..116], 0);
+    put_octal(&mut header[116..124], 0);
+    put_octal(&mut header[124..136], data.len() as u64);
+    put_octal(&mut header[136..148], mtime.max(0) as u64);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    let checksum = format!("{:06o}\0 ", header_checksum(&header));
+    header[148..156].copy_from_slice(checksum.as_bytes());
+    out.write_all(&header)?;
+    out.write_all(data)?;
+    out.write_all(&vec![0u8; data.len().next_multiple_of(BLOCK_SIZE) - data.len()])
+}
+
+/// Read the next header of a tar archive, returning the name and size of
+/// the file following it, None at the end of the archive
+fn read_header(input: &mut impl Read) -> Result<Option<(String, u64)>> {
+    let mut header = [0u8; BLOCK_SIZE];
+    input.read_exact(&mut header)?;
+    if header.iter().all(|&b| b == 0) {
+        return Ok(None);
+    }
+    if get_octal(&header[148..156])? != header_checksum(&header) {
+        return Err(invalid("corrupted tar header"));
+    }
+    let name: String = header[..100].iter()
+                                    .take_while(|&&b| b != 0)
+                                    .map(|&b| b as char)
+                                    .collect();
+    Ok(Some((name, get_octal(&header[124..136])?)))
+}
+
+/// Read or skip the data of a file of a tar archive along with its padding.
+/// The data is read up to the size given by the header, which is not trusted
+/// for allocating it beforehand
+fn read_data(input: &mut impl Read, name: &str, size: u64, keep: bool) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let read = match keep {
+        true => input.take(size).read_to_end(&mut data)? as u64,
+        false => std::io::copy(&mut input.take(size), &mut std::io::sink())?,
+    };
+    if read != size {
+        return Err(Error::new(ErrorKind::UnexpectedEof, format!("{name} truncated")));
+    }
+    // Skip the padding to the next block
+    let mut padding = [0u8; BLOCK_SIZE];
+    input.read_exact(&mut padding[..(size.next_multiple_of(BLOCK_SIZE as u64) - size) as usize])?;
+    Ok(data)
+}
+
+/// Read the next file of a tar archive, None at the end of the archive
+fn read_entry(input: &mut impl Read) -> Result<Option<(String, Vec<u8>)>> {
+    match read_header(input)? {
+        Some((name, size)) => read_data(input, &name, size, true).map(|data| Some((name, data))),
+        None => Ok(None),
+    }
+}
+
+/// Check whether some data starts with a tar header, i.e. is an archive
+pub fn is_archive(data: &[u8]) -> bool {
+    data.len() >= BLOCK_SIZE && data[257..262] == *b"ustar"
+}
+
+/// Write an archive holding the manifest and the images it lists
+pub fn write(out: &mut impl Write, manifest: &Manifest, images: &[Vec<u8>]) -> Result<()> {
+    let mtime = manifest.created.timestamp();
+    write_entry(out, MANIFEST_NAME, manifest.to_string().as_bytes(), mtime)?;
+    for (mem, image) in manifest.memories.iter().zip(images) {
+        write_entry(out, &mem.file, image, mtime)?;
+    }
+    // End of archive marker
+    out.write_all(&[0u8; 2 * BLOCK_SIZE])
+}
+
+fn read_manifest_entry(input: &mut impl Read) -> Result<Manifest> {
+    match read_entry(input)? {
+        Some((name, data)) if name == MANIFEST_NAME => {
+            Manifest::parse(&String::from_utf8_lossy(&data))
+        },
+        _ => Err(invalid("not a backup archive: manifest not found")),
+    }
+}
+
+/// Read an archive, checking every image against the digest in the manifest
+pub fn read(input: &mut impl Read) -> Result<Backup> {
+    let manifest = read_manifest_entry(input)?;
+    let mut files = Vec::new();
+    while let Some(entry) = read_entry(input)? {
+        files.push(entry);
+    }
+    let mut images = Vec::new();
+    for mem in &manifest.memories {
+        let (_, image) = files.iter()
+                              .find(|(name, _)| *name == mem.file)
+                              .ok_or(invalid(format!("{} missing from archive", mem.file)))?;
+        check_image(mem, image)?;
+        images.push(image.clone());
+    }
+    Ok(Backup { manifest, images })
+}
+
+fn check_image(mem: &MemoryEntry, image: &[u8]) -> Result<()> {
+    match image.len() == mem.info.size as usize && Algorithm::SHA256.digest(image) == mem.sha256 {
+        true => Ok(()),
+        false => Err(invalid(format!("{} does not match its digest in the manifest", mem.file))),
+    }
+}
+
+/// Read only the manifest of an archive file
+pub fn read_manifest(path: &Path) -> Result<Manifest> {
+    read_manifest_entry(&mut File::open(path)?)
+}
+
+/// Read the image of a single memory listed in the manifest of an archive
+/// file, skipping the other files, and check it against its digest
+pub fn read_image(path: &Path, mem: &MemoryEntry) -> Result<Vec<u8>> {
+    let mut input = std::io::BufReader::new(File::open(path)?);
+    while let Some((name, size)) = read_header(&mut input)? {
+        let data = read_data(&mut input, &name, size, name == mem.file)?;
+        if name == mem.file {
+            check_image(mem, &data)?;
+            return Ok(data);
+        }
+    }
+    Err(invalid(format!("{} missing from archive", mem.file)))
+}
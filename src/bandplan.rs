@@ -0,0 +1,206 @@
+//! This module handles the band plans used to validate transmit frequencies
+
+/*!
+A band plan is a list of frequency ranges where transmission is allowed.
+The IARU region plans are built in, custom plans can be loaded from a text
+file containing one band per line:
+
+```text
+# START   END     NAME
+144M      146M    2m
+430.000   440.000 70cm
+```
+
+Frequencies follow the syntax accepted by [`Frequency`], both ends of each
+band are included in the allocation.
+*/
+
+use std::fmt;
+use std::fs::read_to_string;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::cat;
+use crate::freq::Frequency;
+
+/// Environment variable holding the default band plan
+pub const BANDPLAN_ENV: &str = "RTXLINK_BANDPLAN";
+
+/// Amateur allocations common to all IARU regions, in kHz
+const IARU_COMMON: [(u32, u32, &str); 9] = [
+    (5_351, 5_366, "60m"),
+    (10_100, 10_150, "30m"),
+    (14_000, 14_350, "20m"),
+    (18_068, 18_168, "17m"),
+    (21_000, 21_450, "15m"),
+    (24_890, 24_990, "12m"),
+    (28_000, 29_700, "10m"),
+    (144_000, 146_000, "2m"),
+    (1_240_000, 1_300_000, "23cm"),
+];
+
+/// Region specific amateur allocations, in kHz
+const IARU_REGION_1: [(u32, u32, &str); 6] = [
+    (1_810, 2_000, "160m"),
+    (3_500, 3_800, "80m"),
+    (7_000, 7_200, "40m"),
+    (50_000, 52_000, "6m"),
+    (70_000, 70_500, "4m"),
+    (430_000, 440_000, "70cm"),
+];
+
+const IARU_REGION_2: [(u32, u32, &str); 8] = [
+    (1_800, 2_000, "160m"),
+    (3_500, 4_000, "80m"),
+    (7_000, 7_300, "40m"),
+    (50_000, 54_000, "6m"),
+    (146_000, 148_000, "2m"),
+    (222_000, 225_000, "1.25m"),
+    (420_000, 450_000, "70cm"),
+    (902_000, 928_000, "33cm"),
+];
+
+const IARU_REGION_3: [(u32, u32, &str); 6] = [
+    (1_800, 2_000, "160m"),
+    (3_500, 3_900, "80m"),
+    (7_000, 7_200, "40m"),
+    (50_000, 54_000, "6m"),
+    (146_000, 148_000, "2m"),
+    (430_000, 440_000, "70cm"),
+];
+
+pub struct Band {
+    pub start: Frequency,
+    pub end:   Frequency,
+    pub name:  String,
+}
+
+pub struct BandPlan {
+    pub name:  String,
+    pub bands: Vec<Band>,
+}
+
+impl Band {
+    pub fn contains(&self, freq: Frequency) -> bool {
+        self.start <= freq && freq <= self.end
+    }
+}
+
+impl fmt::Display for Band {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({} - {})", self.name, self.start, self.end)
+    }
+}
+
+impl BandPlan {
+    /// Build a band plan from a table of allocations expressed in kHz
+    fn from_table(name: &str, tables: &[&[(u32, u32, &str)]]) -> BandPlan {
+        let bands = tables.iter()
+                          .flat_map(|t| t.iter())
+                          .map(|&(start, end, name)| Band {
+                              start: Frequency::from_hz(start * 1000),
+                              end: Frequency::from_hz(end * 1000),
+                              name: String::from(name),
+                          })
+                          .collect();
+        BandPlan { name: String::from(name), bands }
+    }
+
+    /// Plan made of the parts of the bands which are also allocated in another
+    fn intersect(&self, other: &BandPlan) -> BandPlan {
+        let bands = self.bands.iter()
+                              .flat_map(|a| other.bands.iter().map(move |b| (a, b)))
+                              .filter(|(a, b)| a.start.max(b.start) <= a.end.min(b.end))
+                              .map(|(a, b)| Band { start: a.start.max(b.start), end: a.end.min(b.end), name: a.name.clone() })
+                              .collect();
+        BandPlan { name: self.name.clone(), bands }
+    }
+
+    /// Built-in IARU band plan for a region, or if None the allocations
+    /// shared by all regions, so that it is safe wherever the radio is used
+    pub fn iaru(region: Option<u8>) -> Option<BandPlan> {
+        match region {
+            Some(1) => Some(BandPlan::from_table("IARU Region 1", &[&IARU_COMMON, &IARU_REGION_1])),
+            Some(2) => Some(BandPlan::from_table("IARU Region 2", &[&IARU_COMMON, &IARU_REGION_2])),
+            Some(3) => Some(BandPlan::from_table("IARU Region 3", &[&IARU_COMMON, &IARU_REGION_3])),
+            None => {
+                let region = |table: &[(u32, u32, &str)]| BandPlan::from_table("", &[table]);
+                let shared = region(&IARU_REGION_1).intersect(&region(&IARU_REGION_2))
+                                                   .intersect(&region(&IARU_REGION_3));
+                let mut plan = BandPlan::from_table("IARU", &[&IARU_COMMON]);
+                plan.bands.extend(shared.bands);
+                Some(plan)
+            },
+            _ => None,
+        }
+    }
+
+    /// Parse a band plan from its textual representation
+    pub fn parse(name: &str, text: &str) -> Result<BandPlan> {
+        let mut bands = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let bad_line = |why: String| Error::new(ErrorKind::InvalidData,
+                                                     format!("{}:{}: {}", name, i + 1, why));
+            if fields.len() < 2 {
+                return Err(bad_line(String::from("expected START END [NAME]")));
+            }
+            let start: Frequency = fields[0].parse().map_err(|e| bad_line(format!("{e}")))?;
+            let end: Frequency = fields[1].parse().map_err(|e| bad_line(format!("{e}")))?;
+            if end < start {
+                return Err(bad_line(String::from("band ends before it starts")));
+            }
+            let name = match fields.len() {
+                2 => format!("{start} - {end}"),
+                _ => fields[2..].join(" "),
+            };
+            bands.push(Band { start, end, name });
+        }
+        Ok(BandPlan { name: String::from(name), bands })
+    }
+
+    /// Load a band plan: r1, r2, r3 and iaru select the built-in plans,
+    /// anything else is treated as the path of a band plan file
+    pub fn load(spec: &str) -> Result<BandPlan> {
+        let region = match spec.to_ascii_lowercase().as_str() {
+            "iaru" => Some(None),
+            "r1" | "region1" => Some(Some(1)),
+            "r2" | "region2" => Some(Some(2)),
+            "r3" | "region3" => Some(Some(3)),
+            _ => None,
+        };
+        match region {
+            Some(region) => Ok(BandPlan::iaru(region).unwrap()),
+            None => BandPlan::parse(spec, &read_to_string(spec)?),
+        }
+    }
+
+    /// Band containing a given frequency, if any
+    pub fn band(&self, freq: Frequency) -> Option<&Band> {
+        self.bands.iter().find(|b| b.contains(freq))
+    }
+}
+
+/// Check that a transmit frequency is within the band plan, if given, and
+/// within the band limits reported by the radio, if it supports reporting them
+pub fn check_tx(freq: Frequency, plan: Option<&BandPlan>) -> Result<()> {
+    if let Ok(limits) = cat::band_limits() {
+        if !limits.is_empty() && !limits.iter().any(|b| b.contains(freq)) {
+            let supported: Vec<String> = limits.iter().map(|b| b.to_string()).collect();
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("{freq} is not supported by the radio, supported bands: {}",
+                                          supported.join(", "))));
+        }
+    }
+    if let Some(plan) = plan {
+        if plan.band(freq).is_none() {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("{freq} is outside the {} band plan, use --force to transmit anyway",
+                                          plan.name)));
+        }
+    }
+    Ok(())
+}
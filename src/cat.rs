@@ -1,19 +1,32 @@
 //! This module handles the Computer Aided Transceiver portion of rtxlink
 
 use byteorder::{ByteOrder, LittleEndian};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::io;
 use std::str;
+use std::sync::{Arc, Mutex};
 
+use crate::bandplan;
+use crate::bandplan::Band;
+use crate::bandplan::BandPlan;
+use crate::freq::Frequency;
+use crate::gps::GpsInfo;
+use crate::hex;
+use crate::info::RadioInfo;
 use crate::link::Errno;
 use crate::link::Frame;
 use crate::link::Link;
 use crate::link::Protocol;
 
 /// CAT Protocol opcodes
+#[allow(clippy::upper_case_acronyms)]
 enum Opcode {
-    GET  = 0x47, // G
-    SET  = 0x53, // S
-    DATA = 0x44, // D
-    ACK  = 0x41, // A
+    GET    = 0x47, // G
+    SET    = 0x53, // S
+    DATA   = 0x44, // D
+    ACK    = 0x41, // A
+    NOTIFY = 0x4E, // N
 }
 
 impl TryFrom<u8> for Opcode {
@@ -25,125 +38,414 @@ impl TryFrom<u8> for Opcode {
             x if x == Opcode::SET as u8 => Ok(Opcode::SET),
             x if x == Opcode::DATA as u8 => Ok(Opcode::DATA),
             x if x == Opcode::ACK as u8 => Ok(Opcode::ACK),
+            x if x == Opcode::NOTIFY as u8 => Ok(Opcode::NOTIFY),
             _ => Err(()),
         }
     }
 }
 
 /// CAT Protocol IDs
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 enum ID {
     INFO         = 0x494E, // IN
     FREQRX       = 0x5246, // RF
     FREQTX       = 0x5446, // TF
     FILETRANSFER = 0x4654, // FT
+    BANDLIMITS   = 0x424C, // BL
+    TIME         = 0x544D, // TM
+    GPS          = 0x4750, // GP
+    NOTIFY       = 0x4E54, // NT
+    PTT          = 0x5054, // PT
+    SQUELCH      = 0x5351, // SQ
+    CHANNEL      = 0x4348, // CH
+    BATTERY      = 0x4241, // BA
+    RSSI         = 0x5253, // RS
+    TEMPERATURE  = 0x5445, // TE
+    OPMODE       = 0x4F4D, // OM
 }
 
-/// Convert Hertz in MegaHertz
-const HZ_IN_MHZ: f64 = 1000000.0;
+/// Radio operating modes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpMode {
+    NONE,
+    FM,
+    DMR,
+    M17,
+    UNKNOWN(u8),
+}
 
-/// CAT GET request
-fn get(id: ID) -> Vec<u8> {
-    let mut link = Link::acquire();
+impl From<u8> for OpMode {
+    fn from(v: u8) -> OpMode {
+        match v {
+            0 => OpMode::NONE,
+            1 => OpMode::FM,
+            2 => OpMode::DMR,
+            3 => OpMode::M17,
+            x => OpMode::UNKNOWN(x),
+        }
+    }
+}
 
-    let cmd: Vec<u8> = vec![Opcode::GET as u8,
-                            ((id as u16 >> 8) & 0xff) as u8,
-                            (id as u16 & 0xff) as u8];
-    let frame = Frame{proto: Protocol::CAT, data: cmd};
-    link.send(frame);
+impl fmt::Display for OpMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpMode::UNKNOWN(x) => write!(f, "unknown ({x})"),
+            mode => write!(f, "{mode:?}"),
+        }
+    }
+}
+
+/// Kinds of notifications the radio can send, the discriminant is the bit
+/// enabling them in the subscription mask
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    FreqRx     = 0,
+    FreqTx     = 1,
+    Ptt        = 2,
+    Squelch    = 3,
+    Channel    = 4,
+    LowBattery = 5,
+}
+
+impl EventKind {
+    pub const ALL: [EventKind; 6] = [EventKind::FreqRx, EventKind::FreqTx, EventKind::Ptt,
+                                     EventKind::Squelch, EventKind::Channel, EventKind::LowBattery];
+}
+
+/// Notification sent by the radio
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    FreqRx(Frequency),
+    FreqTx(Frequency),
+    Ptt(bool),        // Transmitting
+    Squelch(bool),    // Squelch open
+    Channel(u16),     // Channel index
+    LowBattery(u16),  // Battery voltage in mV
+}
+
+impl Event {
+    /// Decode the content of a CAT NOTIFY frame, opcode included
+    pub fn from_notification(data: &[u8]) -> Option<Event> {
+        if data.len() < 3 || data[0] != Opcode::NOTIFY as u8 {
+            return None;
+        }
+        let id = ((data[1] as u16) << 8) | data[2] as u16;
+        let payload = &data[3..];
+        let flag = || payload.first().map(|&b| b != 0);
+        let word = || (payload.len() >= 2).then(|| LittleEndian::read_u16(payload));
+        let freq = || (payload.len() >= 4).then(|| Frequency::from_hz(LittleEndian::read_u32(payload)));
+        match id {
+            x if x == ID::FREQRX as u16 => freq().map(Event::FreqRx),
+            x if x == ID::FREQTX as u16 => freq().map(Event::FreqTx),
+            x if x == ID::PTT as u16 => flag().map(Event::Ptt),
+            x if x == ID::SQUELCH as u16 => flag().map(Event::Squelch),
+            x if x == ID::CHANNEL as u16 => word().map(Event::Channel),
+            x if x == ID::BATTERY as u16 => word().map(Event::LowBattery),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::FreqRx(freq) => write!(f, "Rx frequency {freq}"),
+            Event::FreqTx(freq) => write!(f, "Tx frequency {freq}"),
+            Event::Ptt(true) => write!(f, "PTT pressed"),
+            Event::Ptt(false) => write!(f, "PTT released"),
+            Event::Squelch(true) => write!(f, "Squelch open"),
+            Event::Squelch(false) => write!(f, "Squelch closed"),
+            Event::Channel(ch) => write!(f, "Channel {ch}"),
+            Event::LowBattery(mv) => write!(f, "Low battery {}.{:03} V", mv / 1000, mv % 1000),
+        }
+    }
+}
 
-    // Loop until we get a message of the right protocol
-    let mut frame: Frame;
+/// Function called on every notification received from the radio
+pub type EventHandler = Arc<dyn Fn(&Event) + Send + Sync>;
+
+static EVENT_HANDLERS: Mutex<Vec<EventHandler>> = Mutex::new(Vec::new());
+
+/// Pass the events received to the registered event handlers. It is called
+/// once the link is released and without holding the handler list, so that
+/// handlers may send requests or register other handlers
+pub(crate) fn dispatch(events: Vec<Event>) {
+    if events.is_empty() {
+        return;
+    }
+    let handlers = EVENT_HANDLERS.lock().unwrap().clone();
+    for event in &events {
+        for handler in &handlers {
+            handler(event);
+        }
+    }
+}
+
+/// Read frames until a CAT reply arrives, queueing the notifications
+/// received meanwhile to be dispatched afterwards
+fn wait_reply(link: &mut Link, events: &mut Vec<Event>) -> io::Result<Frame> {
     loop {
-        frame = link.receive().expect("Error while reading frame");
+        let frame = link.receive()?;
         match frame.proto {
-            Protocol::CAT => break,
+            Protocol::CAT if frame.data.first() == Some(&(Opcode::NOTIFY as u8)) => {
+                events.extend(Event::from_notification(&frame.data));
+            },
+            Protocol::CAT => break Ok(frame),
             _ => (),
         };
     }
-    let mut data = frame.data;
-    let opcode = Opcode::try_from(data[0]).expect("Opcode not implemented!");
-    match opcode {
-        Opcode::ACK => match data[1] {
-            0 => (),
-            status => println!("Error in GET request: {:?}", Errno::try_from(status).unwrap()),
-        }, // Error?
-        Opcode::DATA => { data.remove(0); () }, // Correct response!
-        _ => panic!("Error while parsing GET response"),
-    };
-    link.release();
-    data
+}
+
+/// Error for a reply which cannot be parsed, carrying its raw content
+fn unexpected_reply(request: &str, data: &[u8]) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+                   format!("unexpected reply to {request} request: [{}]", hex::encode(data)))
+}
+
+/// CAT GET request
+fn get(id: ID) -> io::Result<Vec<u8>> {
+    get_raw(id as u16)
 }
 
 /// CAT SET request
-fn set(id: ID, data: &[u8]) {
+fn set(id: ID, data: &[u8]) -> io::Result<()> {
+    set_raw(id as u16, data)
+}
+
+/// CAT GET request for an arbitrary ID, returning the reply data
+pub fn get_raw(id: u16) -> io::Result<Vec<u8>> {
+    let mut link = Link::acquire();
+
+    let cmd: Vec<u8> = vec![Opcode::GET as u8,
+                            ((id >> 8) & 0xff) as u8,
+                            (id & 0xff) as u8];
+    let frame = Frame{proto: Protocol::CAT, data: cmd};
+    link.send(frame);
+
+    let mut events = Vec::new();
+    let frame = wait_reply(&mut link, &mut events);
+    link.release();
+    dispatch(events);
+    let mut data = frame?.data;
+    match data.first().map(|&op| Opcode::try_from(op)) {
+        Some(Ok(Opcode::ACK)) if data.len() >= 2 => match data[1] {
+            0 => Ok(vec![]),
+            status => Err(Errno::error(status)),
+        }, // Error?
+        Some(Ok(Opcode::DATA)) => { data.remove(0); Ok(data) }, // Correct response!
+        _ => Err(unexpected_reply("GET", &data)),
+    }
+}
+
+/// CAT SET request for an arbitrary ID
+pub fn set_raw(id: u16, data: &[u8]) -> io::Result<()> {
     let mut link = Link::acquire();
 
     let mut cmd: Vec<u8> = vec![Opcode::SET as u8,
-                                ((id as u16 >> 8) & 0xff) as u8,
-                                (id as u16 & 0xff) as u8];
+                                ((id >> 8) & 0xff) as u8,
+                                (id & 0xff) as u8];
     cmd.extend(data);
     let frame = Frame{proto: Protocol::CAT, data: cmd};
     link.send(frame);
 
-    let mut frame: Frame;
-    // Loop until we get a message of the right protocol
-    loop {
-        frame = link.receive().expect("Error while reading frame");
-        match frame.proto {
-            Protocol::CAT => break,
-            _ => (),
-        };
-    }
-    let data = frame.data;
-    let opcode = Opcode::try_from(data[0]).expect("Opcode not implemented!");
-    match opcode {
-        Opcode::ACK => match data[1] {
-            0 => (),
-            status => println!("Error in SET request: {:?}", Errno::try_from(status).unwrap()),
-        }, // Error?
-        _ => panic!("Error while parsing SET response"),
-    };
+    let mut events = Vec::new();
+    let frame = wait_reply(&mut link, &mut events);
     link.release();
+    dispatch(events);
+    let data = frame?.data;
+    match data.first().map(|&op| Opcode::try_from(op)) {
+        Some(Ok(Opcode::ACK)) if data.len() >= 2 => match data[1] {
+            0 => Ok(()),
+            status => Err(Errno::error(status)),
+        }, // Error?
+        _ => Err(unexpected_reply("SET", &data)),
+    }
 }
 
 /// CAT GET radio info
-pub fn info() -> String {
-    let data: Vec<u8> = get(ID::INFO);
+pub fn info() -> io::Result<RadioInfo> {
+    let data: Vec<u8> = get(ID::INFO)?;
     match str::from_utf8(&data) {
-        Ok(name) => String::from(name),
-        Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
+        Ok(info) => Ok(RadioInfo::parse(info)),
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid UTF-8 sequence: {e}"))),
+    }
+}
+
+/// CAT GET receive or transmit frequency
+pub fn get_freq(is_tx: bool) -> io::Result<Frequency> {
+    let id = if is_tx { ID::FREQTX } else { ID::FREQRX };
+    let data: Vec<u8> = get(id)?;
+    if data.len() < 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed frequency"));
     }
+    Ok(Frequency::from_hz(LittleEndian::read_u32(&data)))
 }
 
-/// CAT GET or SET radio frequency
-pub fn freq(data: Option<String>, is_tx: bool) {
+/// CAT SET receive or transmit frequency
+pub fn set_freq(freq: Frequency, is_tx: bool) -> io::Result<()> {
     let id = if is_tx { ID::FREQTX } else { ID::FREQRX };
+    let mut data: [u8; 4] = [0, 0, 0, 0];
+    LittleEndian::write_u32(&mut data, freq.hz());
+    set(id, &data)
+}
+
+/// CAT GET or SET radio frequency, transmit frequencies are checked against
+/// the band plan if one is given
+pub fn freq(data: Option<String>, is_tx: bool, plan: Option<&BandPlan>) -> io::Result<()> {
     // If user supplied no data print frequency, otherwise set
     match data {
         // GET
         None => {
-            let data: Vec<u8> = get(id);
-            let freq: u32 = LittleEndian::read_u32(&data);
-            let freq: f64 = freq as f64 / HZ_IN_MHZ;
+            let freq = get_freq(is_tx)?;
             match is_tx {
-                true => println!("Tx: {freq} MHz"),
-                false => println!("Rx: {freq} MHz"),
+                true => println!("Tx: {freq}"),
+                false => println!("Rx: {freq}"),
             };
         },
         // SET
         Some(data) => {
-            let freq: f64 = data.parse::<f64>().unwrap();
-            let freq: u32 = (freq * HZ_IN_MHZ) as u32;
-            let mut data: [u8; 4] = [0, 0, 0, 0];
-            LittleEndian::write_u32(&mut data, freq);
-            set(id, &data);
+            let freq: Frequency = data.parse().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("invalid frequency \"{data}\": {e}"))
+            })?;
+            if is_tx {
+                bandplan::check_tx(freq, plan)?;
+            }
+            set_freq(freq, is_tx)?;
         },
     };
+    Ok(())
+}
+
+/// CAT GET band limits supported by the radio hardware, each band is
+/// reported as a pair of 32 bit little endian start and end frequencies
+pub fn band_limits() -> io::Result<Vec<Band>> {
+    let data = get(ID::BANDLIMITS)?;
+    if data.len() % 8 != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed band limits"));
+    }
+    let limits = data.chunks(8)
+                     .map(|b| {
+                         let start = Frequency::from_hz(LittleEndian::read_u32(&b[0..4]));
+                         let end = Frequency::from_hz(LittleEndian::read_u32(&b[4..8]));
+                         Band { start, end, name: String::from("radio") }
+                     })
+                     .collect();
+    Ok(limits)
+}
+
+/// Decode a time as sent by the radio, a 64 bit little endian UNIX timestamp
+pub(crate) fn decode_time(data: &[u8]) -> io::Result<DateTime<Utc>> {
+    if data.len() < 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed time"));
+    }
+    DateTime::from_timestamp(LittleEndian::read_i64(data), 0)
+        .ok_or(io::Error::new(io::ErrorKind::InvalidData, "invalid time"))
+}
+
+pub(crate) fn encode_time(time: DateTime<Utc>) -> [u8; 8] {
+    let mut data: [u8; 8] = [0; 8];
+    LittleEndian::write_i64(&mut data, time.timestamp());
+    data
+}
+
+/// Describe the drift of the radio clock from the host clock, in whole seconds
+pub fn drift(radio: DateTime<Utc>, host: DateTime<Utc>) -> String {
+    match (radio - host).num_seconds() {
+        0 => String::from("none"),
+        d if d > 0 => format!("radio {d} s ahead"),
+        d => format!("radio {} s behind", -d),
+    }
+}
+
+/// CAT GET real time clock
+pub fn get_time() -> io::Result<DateTime<Utc>> {
+    decode_time(&get(ID::TIME)?)
+}
+
+/// CAT SET real time clock
+pub fn set_time(time: DateTime<Utc>) -> io::Result<()> {
+    set(ID::TIME, &encode_time(time))
+}
+
+/// CAT GET GPS position and fix status
+pub fn gps() -> io::Result<GpsInfo> {
+    GpsInfo::from_bytes(&get(ID::GPS)?)
+}
+
+/// CAT GET a little endian value of N bytes
+fn get_le<const N: usize>(id: ID) -> io::Result<[u8; N]> {
+    let data = get(id)?;
+    data.get(..N)
+        .and_then(|d| <[u8; N]>::try_from(d).ok())
+        .ok_or(io::Error::new(io::ErrorKind::InvalidData, "reply too short"))
+}
+
+/// CAT GET received signal strength, in dBm
+pub fn rssi() -> io::Result<i16> {
+    get_le::<2>(ID::RSSI).map(i16::from_le_bytes)
+}
+
+/// CAT GET battery voltage, in mV
+pub fn battery() -> io::Result<u16> {
+    get_le::<2>(ID::BATTERY).map(u16::from_le_bytes)
+}
+
+/// CAT GET radio temperature, in degrees Celsius
+pub fn temperature() -> io::Result<f32> {
+    // Sent as tenths of degree
+    get_le::<2>(ID::TEMPERATURE).map(|t| i16::from_le_bytes(t) as f32 / 10.0)
+}
+
+/// CAT GET operating mode
+pub fn opmode() -> io::Result<OpMode> {
+    get_le::<1>(ID::OPMODE).map(|m| OpMode::from(m[0]))
+}
+
+/// Register a function to be called on every notification received
+pub fn on_event(handler: EventHandler) {
+    EVENT_HANDLERS.lock().unwrap().push(handler);
+}
+
+/// CAT SET notification subscription, the radio sends only the kinds of
+/// notifications listed, an empty list disables them
+pub fn subscribe(events: &[EventKind]) -> io::Result<()> {
+    let mask = events.iter().fold(0u16, |mask, &e| mask | 1 << e as u16);
+    let mut data: [u8; 2] = [0; 2];
+    LittleEndian::write_u16(&mut data, mask);
+    set(ID::NOTIFY, &data)
+}
+
+/// Handle the notifications already received, without waiting for more,
+/// returning how many frames were processed. Only whole frames are read, so
+/// that a frame still being received does not block
+pub fn poll_events() -> io::Result<usize> {
+    let mut link = Link::acquire();
+    let mut count = 0;
+    let mut events = Vec::new();
+    let result = loop {
+        match link.frame_ready() {
+            Ok(true) => (),
+            Ok(false) => break Ok(count),
+            Err(e) => break Err(e),
+        }
+        match link.receive() {
+            Ok(frame) => {
+                if let Protocol::CAT = frame.proto {
+                    events.extend(Event::from_notification(&frame.data));
+                }
+            },
+            Err(kind) => break Err(io::Error::from(kind)),
+        }
+        count += 1;
+    };
+    link.release();
+    dispatch(events);
+    result
 }
 
-/// CAT SET file transfer mode
-pub fn ftm() {
+/// CAT SET file transfer mode, use fmp::reset() to leave it
+pub fn ftm() -> io::Result<()> {
     let data: [u8; 0] = [];
-    set(ID::FILETRANSFER, &data);
+    set(ID::FILETRANSFER, &data)
 }
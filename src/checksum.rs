@@ -0,0 +1,99 @@
+//! This module computes the CRC32 and SHA-256 checksums used to compare data with the radio
+
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Reversed polynomial of CRC32 (IEEE 802.3)
+const POLY: u32 = 0xEDB8_8320;
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Incremental CRC32 computation, for data that does not fit in memory
+#[derive(Copy, Clone)]
+pub struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { state: 0xFFFF_FFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.state = TABLE[((self.state ^ byte as u32) & 0xff) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+/// CRC32 of a block of data
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// CRC32 of the content of a file
+pub fn crc32_file(path: &Path) -> io::Result<u32> {
+    let mut file = File::open(path)?;
+    let mut crc = Crc32::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(crc.finish()),
+            n => crc.update(&buffer[..n]),
+        }
+    }
+}
+
+/// Checksum algorithms the radio can compute over a memory region
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Algorithm {
+    CRC32  = 0x00,  // 4 Bytes, little endian
+    SHA256 = 0x01,  // 32 Bytes
+}
+
+impl Algorithm {
+    pub fn digest_size(&self) -> usize {
+        match self {
+            Algorithm::CRC32 => 4,
+            Algorithm::SHA256 => 32,
+        }
+    }
+
+    /// Digest of a block of data, as computed by the radio
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::CRC32 => crc32(data).to_le_bytes().to_vec(),
+            Algorithm::SHA256 => Sha256::digest(data).to_vec(),
+        }
+    }
+}
@@ -1,7 +1,6 @@
 //! This module handles the Data Transfer Protocol portion of rtxlink
 
-use text_colorizer::*;
-use std::fs::{File, read};
+use std::fs::File;
 use std::io::Write;
 use std::io::{Error, ErrorKind};
 use std::sync::mpsc::Sender;
@@ -12,7 +11,7 @@ use crate::link::Link;
 use crate::link::Protocol;
 
 const DAT_FRAME_SIZE: usize = 1024;
-const DAT_PAYLOAD_SIZE: usize = DAT_FRAME_SIZE - 2;
+pub const DAT_PAYLOAD_SIZE: usize = DAT_FRAME_SIZE - 2;
 
 /// This function sends an ACK to signal the correct reception of a DAT frame
 pub fn send_ack(link: &mut Link) {
@@ -20,97 +19,157 @@ pub fn send_ack(link: &mut Link) {
     link.send(frame);
 }
 
-/// This function sends an ACK to signal the correct reception of a DAT frame
-pub fn wait_ack() {
+/// This function waits for the ACK of a DAT frame, returning the error
+/// reported by the radio otherwise
+pub fn wait_ack() -> std::io::Result<()> {
     let mut link = Link::acquire();
     // Loop until we get a message of the right protocol
     let mut frame: Frame;
     loop {
-        frame = link.receive().expect("Error while reading frame");
+        frame = match link.receive() {
+            Ok(frame) => frame,
+            Err(kind) => { link.release(); return Err(Error::from(kind)) },
+        };
         match frame.proto {
             Protocol::DAT => break,
             _ => (),
         };
     }
+    link.release();
     // Parse status byte
-    let ack = frame.data[0];
-    match ack {
-        0x06 => (),
-        status => println!("{}: {:?}", "Error".bold().red(), Errno::try_from(status).unwrap()),
+    match frame.data.first() {
+        Some(0x06) => Ok(()),
+        Some(&status) => Err(Errno::error(status)),
+        None => Err(Error::new(ErrorKind::InvalidData, "Error in DAT protocol: empty ACK")),
     }
-    link.release();
 }
 
 /// This function receives data using the DAT protocol
 pub fn receive(file_name: &str, size: usize, progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<()> {
-    let mut receive_size: usize = 0;
-    let mut prev_block: i16 = -1;
-    let mut file = File::create(&file_name)?;
+    let mut file = File::create(file_name)?;
+    let mut link = Link::acquire();
+    let result = receive_blocks(&mut link, &mut file, size, progress);
+    link.release();
+    result
+}
+
+/// This function receives data using the DAT protocol, keeping it in memory
+pub fn receive_data(size: usize, progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(size);
     let mut link = Link::acquire();
+    let result = receive_blocks(&mut link, &mut data, size, progress);
+    link.release();
+    result.map(|_| data)
+}
+
+/// Check the block number of a received DAT frame and its inverse, returning
+/// the data it carries. Block numbers start from 0 and wrap around
+pub(crate) fn block_data(frame: &[u8], block_number: u8) -> std::io::Result<&[u8]> {
+    if frame.len() < 2 {
+        return Err(Error::new(ErrorKind::InvalidData, "Error in DAT protocol receive: truncated block!"));
+    }
+    if frame[0] != block_number || frame[0] as u16 + frame[1] as u16 != 255 {
+        return Err(Error::new(ErrorKind::Other, "Error in DAT protocol receive: bad block indexing!"));
+    }
+    Ok(&frame[2..])
+}
+
+/// State of a DAT receive of known size
+pub(crate) struct Reception {
+    size: usize,
+    received: usize,
+    frames: usize,      // Number of frames accepted so far
+}
+
+impl Reception {
+    pub(crate) fn new(size: usize) -> Reception {
+        Reception { size, received: 0, frames: 0 }
+    }
+
+    /// Check the next frame received and return its data, refusing data past
+    /// the expected size
+    pub(crate) fn accept<'a>(&mut self, frame: &'a [u8]) -> std::io::Result<&'a [u8]> {
+        let data = block_data(frame, self.frames as u8)?;
+        if self.received + data.len() > self.size {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("Error in DAT protocol receive: more than the {} Bytes expected!", self.size)));
+        }
+        self.frames += 1;
+        self.received += data.len();
+        Ok(data)
+    }
+
+    /// Whether all the data was received, empty data still takes a frame
+    /// without data, as sent by frames
+    pub(crate) fn complete(&self) -> bool {
+        self.frames > 0 && self.received == self.size
+    }
+}
+
+fn receive_blocks(link: &mut Link, file: &mut impl Write, size: usize, progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<()> {
+    let mut reception = Reception::new(size);
     // Loop until we get a message of the right protocol
     let mut frame: Frame;
-    send_ack(&mut link);
-    while receive_size < size {
+    send_ack(link);
+    while !reception.complete() {
         loop {
-            frame = link.receive().expect("Error while reading frame");
+            frame = link.receive().map_err(Error::from)?;
             match frame.proto {
                 Protocol::DAT => break,
                 _ => (),
             };
         }
-        // Check sanity of block number and its inverse
-        let block_number = frame.data[0];
-        let inv_block_number = frame.data[1];
-        if (block_number + inv_block_number != 255) ||
-           (block_number != (prev_block + 1) as u8) {
-            return Err(Error::new(ErrorKind::Other, "Error in DAT protocol receive: bad block indexing!"));
-        }
-        prev_block = block_number as i16;
-        receive_size += frame.data.len() - 2;
-        file.write_all(&frame.data[2..])?;
-        send_ack(&mut link);
-        // println!("Received: {receive_size:?}/{size:?}");
+        file.write_all(reception.accept(&frame.data)?)?;
+        send_ack(link);
+        // println!("Received: {:?}/{size:?}", reception.received);
         if progress.is_some() {
-            match progress.unwrap().send((receive_size, size)) {
+            match progress.unwrap().send((reception.received, size)) {
                 Err(e) => println!("Error when logging progress: {e}"),
                 Ok(_) => (),
             }
         }
     }
-    link.release();
     Ok(())
 }
 
-/// This function sends data using the DAT protocol, call this until there is no more data to send
-pub fn send(file_name: &str, size: usize, progress: Option<&Sender<(usize, usize)>>) {
-    let file_content = read(&file_name).expect("Error in reading backup file!");
+/// Split data into DAT frames: block number, its inverse and up to 1022B of
+/// data, block numbers start from 1 and wrap around. Every frame but the last
+/// is full, empty data is sent as a single frame without data so that the
+/// radio does not wait for a frame which never comes
+pub fn frames(data: &[u8]) -> Vec<Vec<u8>> {
+    let chunks: Vec<&[u8]> = match data.is_empty() {
+        true => vec![data],
+        false => data.chunks(DAT_PAYLOAD_SIZE).collect(),
+    };
+    chunks.iter()
+          .enumerate()
+          .map(|(i, chunk)| {
+              let block = (i + 1) as u8;
+              let mut frame = vec![block, 255 - block];
+              frame.extend_from_slice(chunk);
+              frame
+          })
+          .collect()
+}
+
+/// This function sends data using the DAT protocol, waiting for the ACK of each chunk
+pub fn send(file_content: &[u8], progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<()> {
+    let size = file_content.len();
     let mut send_size: usize = 0;
-    if size != file_content.len() {
-        panic!("Backup file does not match with memory size!");
-    }
-    // Send chunks of 1022B
-    for i in 1..(size / DAT_PAYLOAD_SIZE) + 2 {
-        // Set frame counter and reverse frame counter
-        let mut chunk: Vec<u8> = vec![0; DAT_FRAME_SIZE];
-        chunk[0] = i as u8;
-        chunk[1] = 255 - i as u8;
-        let remaining_data: usize = size - (i - 1) * DAT_PAYLOAD_SIZE;
-        let chunk_size: usize = if remaining_data < DAT_PAYLOAD_SIZE {remaining_data} else {DAT_PAYLOAD_SIZE};
-        let start_offset = (i-1) * DAT_PAYLOAD_SIZE;
-        let end_offset = start_offset + chunk_size;
-        chunk[2..chunk_size + 2].copy_from_slice(&file_content[start_offset..end_offset]);
-        chunk.resize(chunk_size, 0);
+    for chunk in frames(file_content) {
+        let chunk_size = chunk.len() - 2;
         let mut link = Link::acquire();
         let frame = Frame{proto: Protocol::DAT, data: chunk};
         link.send(frame);
         link.release();
         send_size += chunk_size;
         if progress.is_some() {
-            match progress.unwrap().send((send_size, chunk_size)) {
+            match progress.unwrap().send((send_size, size)) {
                 Err(e) => println!("Error when logging progress: {e}"),
                 Ok(_) => (),
             }
         }
-        wait_ack();
+        wait_ack()?;
     }
+    Ok(())
 }
@@ -1,47 +1,555 @@
 use url::Url;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result, Write};
 use std::sync::mpsc::Sender;
 use std::env::current_dir;
+use std::fmt;
+use std::path::Path;
 
+use crate::archive;
 use crate::cat;
+use crate::checksum::Algorithm;
 use crate::fmp;
+use crate::freq::Frequency;
+use crate::info::RadioInfo;
 
-pub fn backup(dest_path: Option<String>, progress: Option<&Sender<(usize, usize)>>) {
+/// Run a file transfer with the radio in file transfer mode, resetting the
+/// radio afterwards to leave it, whether the transfer succeeded or not
+fn in_ftm<T>(transfer: impl FnOnce() -> Result<T>) -> Result<T> {
+    cat::ftm()?;
+    let result = transfer();
+    let reset = fmp::reset();
+    let value = result?;
+    reset.map(|_| value)
+}
+
+/// Archive written by a backup
+pub struct BackupArchive {
+    pub path: String,
+    pub memories: usize,    // Number of memories in the archive
+    pub unchanged: usize,   // Memories taken from an identical earlier backup
+}
+
+/// Find the image of a memory with the given SHA-256 digest in the earlier
+/// backups of the radio stored in a directory
+pub(crate) fn find_image(dir: &Path, prefix: &str, mem: &fmp::MemInfo, digest: &[u8]) -> Option<Vec<u8>> {
+    std::fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            name.starts_with(prefix) && name.ends_with(".tar")
+        })
+        .find_map(|path| {
+            let manifest = archive::read_manifest(&path).ok()?;
+            let entry = manifest.memories.iter().find(|m| m.info == *mem && m.sha256 == digest)?;
+            archive::read_image(&path, entry).ok()
+        })
+}
+
+/// Path of a new backup archive in a directory, named after the radio and
+/// the current time
+fn archive_name(dir: &Path, radio: &RadioInfo, suffix: &str) -> String {
+    dir.join(format!("{}_{}{suffix}.tar", radio.id(), chrono::offset::Local::now().format("%d%m%Y-%H%M%S")))
+       .to_string_lossy()
+       .into_owned()
+}
+
+fn save_archive(file_name: &str, manifest: &archive::Manifest, images: &[Vec<u8>]) -> Result<()> {
+    let mut file = BufWriter::new(File::create(file_name)?);
+    archive::write(&mut file, manifest, images).and_then(|_| file.flush()).map_err(|why| {
+        Error::new(why.kind(), format!("Error while storing backup on {}: {}", file_name, why))
+    })
+}
+
+/// Back up all the memories of the radio in a single archive, along with a
+/// manifest describing them. Memories identical to an earlier backup in the
+/// same directory, according to the checksum computed by the radio, are
+/// copied from it instead of being transferred again
+pub fn backup(dest_path: Option<String>, progress: Option<&Sender<(usize, usize)>>) -> Result<BackupArchive> {
     // If it's a URI decode it to a path, default path is .
     let dest_path = match dest_path {
-        Some(x) => Url::parse(&x).unwrap().to_file_path().unwrap(),
-        _ => current_dir().unwrap(),
+        Some(x) => Url::parse(&x).ok()
+                                 .and_then(|url| url.to_file_path().ok())
+                                 .ok_or(Error::new(ErrorKind::InvalidInput, format!("{x} is not a file URI")))?,
+        _ => current_dir()?,
     };
-    let radio_name = cat::info();
-    // Enumerate all the memories, dump each in a separate file
-    let mem_list = fmp::meminfo();
-    // Put the radio in file transfer mode and dump all the memories
-    cat::ftm();
-    for (i, mem) in mem_list.iter().enumerate() {
-        let mut file_name = String::new();
-        file_name.push_str(dest_path.to_str().unwrap());
-        file_name.push_str("/");
-        file_name.push_str(&radio_name);
-        file_name.push_str("_");
-        file_name.push_str(&mem.to_string());
-        file_name.push_str(&chrono::offset::Local::now().format("_%d%m%Y")
-                                                        .to_string());
-        file_name.push_str(".bin");
-        match fmp::dump(i, &mem, &file_name, progress) {
-            Err(why) => panic!("Error while storing backup on {}: {}", file_name, why),
-            Ok(x) => x,
-        }
-    }
-}
-
-pub fn restore(mem_index: Option<String>, src_path: Option<String>, progress: Option<&Sender<(usize, usize)>>) {
+    let radio = cat::info()?;
+    let prefix = format!("{}_", radio.id());
+    let file_name = archive_name(&dest_path, &radio, "");
+    let mut manifest = archive::Manifest::new(radio);
+    // Enumerate all the memories
+    let mem_list = fmp::meminfo()?;
+    // Put the radio in file transfer mode and read all the memories
+    let (images, unchanged) = in_ftm(|| {
+        let mut images = Vec::new();
+        let mut unchanged = 0;
+        for (i, mem) in mem_list.iter().enumerate() {
+            let previous = fmp::checksum(i, mem, None, Algorithm::SHA256).ok()
+                                                                         .and_then(|digest| find_image(&dest_path, &prefix, mem, &digest));
+            let image = match previous {
+                Some(image) => { unchanged += 1; image },
+                None => fmp::read(i, mem, None, progress)?,
+            };
+            manifest.add(i, mem, &image);
+            images.push(image);
+        }
+        Ok((images, unchanged))
+    })?;
+    save_archive(&file_name, &manifest, &images)?;
+    Ok(BackupArchive { path: file_name, memories: images.len(), unchanged })
+}
+
+/// Dump a region of a memory into a file, by default the whole memory
+pub fn dump(mem_index: usize, dest: &str, offset: Option<u32>, length: Option<u32>,
+            progress: Option<&Sender<(usize, usize)>>) -> Result<()> {
+    let mem_list = fmp::meminfo()?;
+    let mem = mem_list.get(mem_index)
+                      .ok_or(Error::new(ErrorKind::InvalidInput, "memory index outside range"))?;
+    let range = fmp::MemRange::new(mem, offset, length)?;
+    in_ftm(|| fmp::dump(mem_index, mem, dest, Some(range), progress))
+}
+
+/// Size of the blocks compared when verifying memories without erase blocks
+const VERIFY_BLOCK_SIZE: usize = 4096;
+
+/// Differences between an image and the data read back from a memory
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub offset: usize,      // Offset of the first differing Byte
+    pub bad_blocks: usize,  // Number of blocks with at least one difference
+}
+
+/// Compare data read back from a memory with the expected image, block by block
+pub fn compare(expected: &[u8], actual: &[u8], block_size: usize) -> Option<Mismatch> {
+    let len = expected.len().max(actual.len());
+    let offset = (0..len).find(|&i| expected.get(i) != actual.get(i))?;
+    let bad_blocks = (0..len.div_ceil(block_size))
+        .filter(|b| {
+            let start = b * block_size;
+            (start..(start + block_size).min(len)).any(|i| expected.get(i) != actual.get(i))
+        })
+        .count();
+    Some(Mismatch { offset, bad_blocks })
+}
+
+/// Read back a memory region just written and compare it with the image
+fn verify_readback(mem_index: usize, mem: &fmp::MemInfo, data: &[u8], range: Option<fmp::MemRange>) -> Result<()> {
+    let readback = fmp::read(mem_index, mem, range, None)?;
+    let block_size = mem.flags.erase_block_size().map(|b| b as usize).unwrap_or(VERIFY_BLOCK_SIZE);
+    match compare(data, &readback, block_size) {
+        None => Ok(()),
+        Some(m) => {
+            let base = range.map(|r| r.offset as usize).unwrap_or(0);
+            Err(Error::new(ErrorKind::InvalidData,
+                           format!("verification failed: first mismatch at offset {:#x}, {} bad blocks of {}B",
+                                   base + m.offset, m.bad_blocks, block_size)))
+        },
+    }
+}
+
+/// Check a memory region just written against the image, comparing the CRC32
+/// computed by the radio. The region is read back if the firmware cannot
+/// compute checksums, or to locate the differences if the checksums differ
+fn verify(mem_index: usize, mem: &fmp::MemInfo, data: &[u8], range: Option<fmp::MemRange>) -> Result<()> {
+    check_digest(fmp::checksum(mem_index, mem, range, Algorithm::CRC32), data,
+                 || verify_readback(mem_index, mem, data, range))
+}
+
+/// Check the CRC32 reported by the radio for the data written, reading the
+/// data back with the given function when needed, see verify
+pub(crate) fn check_digest(digest: Result<Vec<u8>>, data: &[u8], readback: impl FnOnce() -> Result<()>) -> Result<()> {
+    match digest {
+        Ok(digest) if digest == Algorithm::CRC32.digest(data) => Ok(()),
+        Ok(_) => readback().and(Err(Error::new(ErrorKind::InvalidData, "verification failed: checksum mismatch"))),
+        Err(e) if e.kind() == ErrorKind::Unsupported => readback(),
+        Err(e) => Err(e),
+    }
+}
+
+/// Part of an image matching a memory region: the region itself if the image
+/// covers the whole memory, else the whole image if it has the region size
+pub(crate) fn region_data<'a>(mem: &fmp::MemInfo, image: &'a [u8], range: fmp::MemRange) -> Result<&'a [u8]> {
+    match image.len() == mem.size as usize {
+        true => Ok(&image[range.offset as usize..(range.offset + range.length) as usize]),
+        false if image.len() == range.length as usize => Ok(image),
+        false => Err(Error::new(ErrorKind::InvalidInput,
+                                format!("image of {} Bytes, neither the memory nor the region {range} size", image.len()))),
+    }
+}
+
+/// Compare a memory, or a region of it, with an image using the checksum
+/// computed by the radio, without file transfer mode
+pub fn matches(mem_index: usize, image: &[u8], offset: Option<u32>, length: Option<u32>) -> Result<bool> {
+    let mem_list = fmp::meminfo()?;
+    let mem = mem_list.get(mem_index)
+                      .ok_or(Error::new(ErrorKind::InvalidInput, "memory index outside range"))?;
+    let range = fmp::MemRange::new(mem, offset, length)?;
+    let expected = region_data(mem, image, range)?;
+    let digest = fmp::checksum(mem_index, mem, Some(range), Algorithm::SHA256)?;
+    Ok(digest == Algorithm::SHA256.digest(expected))
+}
+
+/// Merge the indices of changed blocks into regions of consecutive blocks,
+/// relative to the start of the data, which is `size` Bytes long
+pub fn merge_blocks(blocks: &[u32], block_size: u32, size: u32) -> Vec<fmp::MemRange> {
+    let mut regions: Vec<fmp::MemRange> = Vec::new();
+    for &block in blocks {
+        let offset = block * block_size;
+        let length = block_size.min(size - offset);
+        match regions.last_mut() {
+            Some(last) if last.offset + last.length == offset => last.length += length,
+            _ => regions.push(fmp::MemRange { offset, length }),
+        }
+    }
+    regions
+}
+
+/// Regions of a memory whose content differs from the data to be written at
+/// the start of the given region, comparing the CRC32 of each block
+/// computed by the radio
+fn changed_regions(mem_index: usize, mem: &fmp::MemInfo, data: &[u8], range: fmp::MemRange) -> Result<Vec<fmp::MemRange>> {
+    let block_size = mem.flags.erase_block_size().unwrap_or(VERIFY_BLOCK_SIZE as u32);
+    let mut changed = Vec::new();
+    for (i, block) in data.chunks(block_size as usize).enumerate() {
+        let block_range = fmp::MemRange { offset: range.offset + i as u32 * block_size, length: block.len() as u32 };
+        let digest = fmp::checksum(mem_index, mem, Some(block_range), Algorithm::CRC32)?;
+        if digest != Algorithm::CRC32.digest(block) {
+            changed.push(i as u32);
+        }
+    }
+    Ok(merge_blocks(&changed, block_size, range.length)
+        .into_iter()
+        .map(|r| fmp::MemRange { offset: range.offset + r.offset, ..r })
+        .collect())
+}
+
+/// Options of a memory restore
+#[derive(Copy, Clone, Default)]
+pub struct RestoreOptions {
+    pub offset: Option<u32>,    // Start of the region to write
+    pub length: Option<u32>,    // Length of the region to write
+    pub verify: bool,           // Check the memory content after writing
+    pub diff: bool,             // Only write the blocks that differ
+    pub force: bool,            // Restore a backup which does not match the radio
+    pub backup: bool,           // Save the memories to an archive before writing
+}
+
+/// Reasons why a backup taken from one radio must not be restored to
+/// another: a different model, or a different serial number if both are known
+pub fn identity_mismatches(radio: &RadioInfo, origin: &RadioInfo) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    if radio.model != origin.model {
+        mismatches.push(format!("backup of a {}, the radio is a {}", origin.model, radio.model));
+    }
+    if let (Some(radio_serial), Some(origin_serial)) = (&radio.serial, &origin.serial) {
+        if radio_serial != origin_serial {
+            mismatches.push(format!("backup of serial number {origin_serial}, the radio is {radio_serial}"));
+        }
+    }
+    mismatches
+}
+
+/// Pick the memory of the radio each image of an archive is restored to: the
+/// memory with the same index if it has the same name and size, else the only
+/// one which does, else the one with the same index and size, which is a
+/// mismatch. Without a memory index read-only memories are skipped
+fn archive_targets(backup: archive::Backup, mem_list: &[fmp::MemInfo], mem_index: Option<usize>,
+                   mismatches: &mut Vec<String>, warnings: &mut Vec<String>) -> Vec<(usize, String, Vec<u8>)> {
+    let mut targets = Vec::new();
+    for (entry, image) in backup.manifest.memories.iter().zip(backup.images) {
+        let same = |mem: &fmp::MemInfo| mem.name == entry.info.name && mem.size == entry.info.size;
+        let matching = match mem_list.get(entry.index).filter(|mem| same(mem)) {
+            Some(_) => Some(entry.index),
+            None => {
+                let mut matching = mem_list.iter().enumerate().filter(|(_, mem)| same(mem)).map(|(i, _)| i);
+                match (matching.next(), matching.next()) {
+                    (Some(i), None) => Some(i),
+                    _ => None,
+                }
+            },
+        };
+        let target = matching.or(mem_list.get(entry.index)
+                                         .filter(|mem| mem.size == entry.info.size)
+                                         .map(|_| entry.index));
+        if mem_index.is_some_and(|m| Some(m) != target) {
+            continue;
+        }
+        let Some(t) = target else {
+            mismatches.push(format!("no memory of the radio matches {:?} from the backup", entry.info));
+            continue;
+        };
+        let mem = &mem_list[t];
+        if mem_index.is_none() && mem.flags.read_only() {
+            continue;
+        }
+        if matching.is_none() {
+            mismatches.push(format!("{} holds {:?}, memory {t} is {:?}", entry.file, entry.info, mem));
+        } else if mem.flags != entry.info.flags {
+            mismatches.push(format!("{} comes from a memory with flags {:#04x}, memory {t} has {:#04x}",
+                                    entry.file, entry.info.flags.0, mem.flags.0));
+        } else if t != entry.index {
+            warnings.push(format!("{} was memory {}, it is restored to memory {t}", entry.file, entry.index));
+        }
+        targets.push((t, entry.file.clone(), image));
+    }
+    targets
+}
+
+/// Region of a memory to write and the data to write there, taken from the
+/// image if it covers the whole memory or else made of the whole image
+fn image_region<'a>(mem: &fmp::MemInfo, image: &'a [u8], options: &RestoreOptions,
+                    src_path: &str) -> Result<(Option<fmp::MemRange>, &'a [u8])> {
+    let (offset, length) = (options.offset, options.length);
+    let whole_image = image.len() == mem.size as usize;
+    let range = match (offset, length) {
+        (None, None) => None,
+        // A region file sets the length by itself
+        (_, None) if !whole_image => Some(fmp::MemRange::new(mem, offset, Some(image.len() as u32))?),
+        _ => Some(fmp::MemRange::new(mem, offset, length)?),
+    };
+    let data = match range {
+        None if !whole_image => return Err(Error::new(ErrorKind::InvalidInput, "Backup file does not match with memory size!")),
+        Some(r) if whole_image => &image[r.offset as usize..(r.offset + r.length) as usize],
+        Some(r) if image.len() != r.length as usize => {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                                  format!("{src_path} is {} Bytes, neither the memory nor the region {r} size", image.len())));
+        },
+        _ => image,
+    };
+    Ok((range, data))
+}
+
+/// Write data to a memory region in file transfer mode, returning the number
+/// of Bytes written
+fn write_region(mem_index: usize, mem: &fmp::MemInfo, data: &[u8], range: Option<fmp::MemRange>,
+                options: &RestoreOptions, progress: Option<&Sender<(usize, usize)>>) -> Result<usize> {
+    let full = range.unwrap_or(fmp::MemRange::whole(mem));
+    let regions = match options.diff {
+        true => match changed_regions(mem_index, mem, data, full) {
+            Ok(regions) => regions,
+            Err(e) if e.kind() == ErrorKind::Unsupported => vec![full],
+            Err(e) => return Err(e),
+        },
+        false => vec![full],
+    };
+    let mut written = 0;
+    for region in regions {
+        let start = (region.offset - full.offset) as usize;
+        let chunk = &data[start..start + region.length as usize];
+        fmp::flash(mem_index, mem, chunk, Some(region), progress)?;
+        if options.verify {
+            verify(mem_index, mem, chunk, Some(region))?;
+        }
+        written += chunk.len();
+    }
+    Ok(written)
+}
+
+/// Memory write planned by a restore
+pub struct RestoreTarget {
+    pub mem_index: usize,
+    pub mem: fmp::MemInfo,
+    pub range: Option<fmp::MemRange>,
+    pub source: String,     // Name of the image
+    data: Vec<u8>,          // Data to write
+}
+
+/// Memory writes of a restore, checked against the radio before any is done
+pub struct RestorePlan {
+    pub radio: RadioInfo,
+    pub origin: Option<archive::Manifest>,  // Manifest of a backup archive, None for a raw image
+    pub targets: Vec<RestoreTarget>,
+    pub warnings: Vec<String>,              // Forced mismatches and checks which could not be done
+    pub backup: Option<String>,             // Archive the memories are saved to before writing
+}
+
+impl fmt::Display for RestorePlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Radio:  {}", self.radio)?;
+        if let Some(origin) = &self.origin {
+            writeln!(f, "Backup: {}, created {} by rtxlink {}", origin.radio,
+                     origin.created.format("%Y-%m-%d %H:%M:%S UTC"), origin.version)?;
+        }
+        for target in &self.targets {
+            write!(f, "[{}]: {:?}", target.mem_index, target.mem)?;
+            if let Some(range) = target.range {
+                write!(f, " {range}")?;
+            }
+            writeln!(f, " <- {}", target.source)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {warning}")?;
+        }
+        match &self.backup {
+            Some(path) => write!(f, "The memories are saved to {path} first"),
+            None => write!(f, "The memories are not saved first"),
+        }
+    }
+}
+
+/// Radio and memory a raw image comes from, according to the name given to it
+/// by the backups of earlier versions: <radio>_<memory>_<size>_<date>.bin,
+/// the memory name having its spaces removed
+pub(crate) fn legacy_origin(src_path: &str) -> Option<(RadioInfo, String, u32)> {
+    let name = Path::new(src_path).file_name()?.to_str()?.strip_suffix(".bin")?;
+    let mut fields = name.rsplitn(4, '_');
+    let date = fields.next()?;
+    let size = fields.next()?.parse().ok()?;
+    let mem = fields.next()?;
+    let radio = fields.next().filter(|r| !r.is_empty())?;
+    match date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()) && !mem.is_empty() {
+        true => Some((RadioInfo::parse(radio), String::from(mem), size)),
+        false => None,
+    }
+}
+
+/// Check an image or a backup archive read from a file against the radio and
+/// its memories, and plan the memory writes restoring it, see plan_restore
+pub(crate) fn plan_image(radio: RadioInfo, mem_list: &[fmp::MemInfo], mem_index: Option<usize>,
+                         src_path: &str, file: &[u8], options: &RestoreOptions) -> Result<RestorePlan> {
+    if mem_index.is_some_and(|m| m >= mem_list.len()) {
+        return Err(Error::new(ErrorKind::InvalidInput, "memory index outside range"));
+    }
+    let mut mismatches = Vec::new();
+    let mut warnings = Vec::new();
+    let (origin, images) = match archive::is_archive(file) {
+        true => {
+            let backup = archive::read(&mut &file[..])?;
+            let manifest = backup.manifest.clone();
+            mismatches.extend(identity_mismatches(&radio, &manifest.radio));
+            if manifest.radio.firmware != radio.firmware {
+                warnings.push(format!("backup taken with firmware {}, the radio runs {}",
+                                      manifest.radio.firmware.as_deref().unwrap_or("unknown"),
+                                      radio.firmware.as_deref().unwrap_or("unknown")));
+            }
+            (Some(manifest), archive_targets(backup, mem_list, mem_index, &mut mismatches, &mut warnings))
+        },
+        false => {
+            let mem_index = mem_index.ok_or(Error::new(ErrorKind::InvalidInput, "memory index not found"))?;
+            // Nothing records where a raw image comes from, at best its name
+            match legacy_origin(src_path) {
+                Some((origin, name, size)) => {
+                    mismatches.push(format!("{src_path} is a raw image, only its name and size can be checked"));
+                    mismatches.extend(identity_mismatches(&radio, &origin));
+                    let mem = &mem_list[mem_index];
+                    if name != mem.name.replace(' ', "") || size != mem.size {
+                        mismatches.push(format!("{src_path} is named after memory {name} of {size} Bytes, \
+                                                 memory {mem_index} is {mem:?}"));
+                    }
+                },
+                None => mismatches.push(format!("{src_path} is a raw image, only its size can be checked")),
+            }
+            (None, vec![(mem_index, String::from(src_path), file.to_vec())])
+        },
+    };
+    if !mismatches.is_empty() && !options.force {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("backup does not match the radio: {}; use --force to restore anyway",
+                                      mismatches.join("; "))));
+    }
+    warnings.splice(0..0, mismatches);
+    match (images.is_empty(), mem_index) {
+        (true, Some(m)) => return Err(Error::new(ErrorKind::InvalidInput, format!("backup holds no image of memory {m}"))),
+        (true, None) => return Err(Error::new(ErrorKind::InvalidInput, "backup holds no writable memory")),
+        (false, _) => (),
+    }
+    if images.len() > 1 && (options.offset.is_some() || options.length.is_some()) {
+        return Err(Error::new(ErrorKind::InvalidInput, "restoring a region requires a memory index"));
+    }
+    let mut targets = Vec::new();
+    for (i, source, image) in images {
+        let mem = &mem_list[i];
+        let (range, data) = image_region(mem, &image, options, &source)?;
+        targets.push(RestoreTarget { mem_index: i, mem: mem.clone(), range, source, data: data.to_vec() });
+    }
+    Ok(RestorePlan { radio, origin, targets, warnings, backup: None })
+}
+
+/// Check an image or a backup archive against the radio and plan the memory
+/// writes restoring it, see restore. Mismatches between the radio and the
+/// origin of a backup are refused, unless forced, in which case they are
+/// reported as warnings. A raw image is always a mismatch, since at best
+/// its name tells where it comes from
+pub fn plan_restore(mem_index: Option<String>, src_path: Option<String>, options: &RestoreOptions) -> Result<RestorePlan> {
     // Parse parameters
-    let mem_index = mem_index.expect("Error: memory index not found!")
-                             .parse::<usize>()
-                             .expect("Error: invalid memory index!");
-    let src_path = src_path.expect("Error: backup file not found!");
-    let mem_list = fmp::meminfo();
-    if mem_index > mem_list.len() {
-        panic!("Error: memory index outsize range!");
-    }
-    fmp::flash(mem_index, &mem_list[mem_index], &src_path, progress);
+    let mem_index = match mem_index {
+        Some(m) => Some(m.parse::<usize>().map_err(|_| Error::new(ErrorKind::InvalidInput, "invalid memory index"))?),
+        None => None,
+    };
+    let src_path = src_path.ok_or(Error::new(ErrorKind::InvalidInput, "backup file not found"))?;
+    let radio = cat::info()?;
+    let mem_list = fmp::meminfo()?;
+    let file = std::fs::read(&src_path)?;
+    let mut plan = plan_image(radio, &mem_list, mem_index, &src_path, &file, options)?;
+    if options.backup {
+        plan.backup = Some(archive_name(&current_dir()?, &plan.radio, "_pre-restore"));
+    }
+    Ok(plan)
+}
+
+/// Carry out a restore plan, returning the number of Bytes written. The
+/// memories are first saved whole if the plan asks for it
+pub fn execute_restore(plan: &RestorePlan, options: &RestoreOptions,
+                       progress: Option<&Sender<(usize, usize)>>) -> Result<usize> {
+    in_ftm(|| {
+        if let Some(path) = &plan.backup {
+            let mut manifest = archive::Manifest::new(plan.radio.clone());
+            let mut images = Vec::new();
+            for target in &plan.targets {
+                let image = fmp::read(target.mem_index, &target.mem, None, None)?;
+                manifest.add(target.mem_index, &target.mem, &image);
+                images.push(image);
+            }
+            save_archive(path, &manifest, &images)?;
+        }
+        let mut written = 0;
+        for target in &plan.targets {
+            written += write_region(target.mem_index, &target.mem, &target.data, target.range, options, progress)?;
+        }
+        Ok(written)
+    })
+}
+
+/// Write an image to a memory, returning the number of Bytes written. The
+/// source is either a raw image, which requires a memory index, or a backup
+/// archive, whose images are written to the memories matching its manifest,
+/// or only to the given memory. With an offset or a length only that region
+/// of a single memory is written, taken from the image if it covers the
+/// whole memory or else made of the whole file. With verify the written data
+/// is checked afterwards; with diff only the blocks whose checksum differs
+/// from the image are written, if the firmware supports it
+pub fn restore(mem_index: Option<String>, src_path: Option<String>, options: &RestoreOptions,
+               progress: Option<&Sender<(usize, usize)>>) -> Result<usize> {
+    let plan = plan_restore(mem_index, src_path, options)?;
+    execute_restore(&plan, options, progress)
+}
+
+/// Set a frequency and read it back to check that the radio accepted it
+fn set_and_verify(freq: Frequency, is_tx: bool) -> Result<()> {
+    cat::set_freq(freq, is_tx)?;
+    let readback = cat::get_freq(is_tx)?;
+    if readback != freq {
+        let dir = if is_tx { "Tx" } else { "Rx" };
+        return Err(Error::other(format!("{dir} frequency set to {freq} but radio reports {readback}")));
+    }
+    Ok(())
+}
+
+/// Set receive and transmit frequencies as a single operation: if any of the
+/// two cannot be set or verified the previous frequencies are restored
+pub fn tune(rx: Frequency, tx: Frequency) -> Result<()> {
+    let prev_rx = cat::get_freq(false)?;
+    let prev_tx = cat::get_freq(true)?;
+    let result = set_and_verify(rx, false).and_then(|_| set_and_verify(tx, true));
+    if let Err(e) = result {
+        let rollback = cat::set_freq(prev_rx, false).and_then(|_| cat::set_freq(prev_tx, true));
+        return match rollback {
+            Ok(_) => Err(Error::new(e.kind(),
+                                    format!("{e}, restored Rx {prev_rx} and Tx {prev_tx}"))),
+            Err(r) => Err(Error::new(e.kind(),
+                                     format!("{e}, failed to restore previous frequencies: {r}"))),
+        };
+    }
+    Ok(())
 }
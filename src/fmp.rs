@@ -1,11 +1,10 @@
 //! This module handles the File Management Protocol portion of rtxlink
 
-use std::ffi::CStr;
 use std::fmt;
 use std::str;
-use text_colorizer::*;
 use std::sync::mpsc::Sender;
 
+use crate::checksum::Algorithm;
 use crate::link::Errno;
 use crate::link::Frame;
 use crate::link::Link;
@@ -13,7 +12,7 @@ use crate::link::Protocol;
 use crate::dat;
 
 /// FMP Protocol Opcodes
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Opcode {
     ACK     = 0x00,
     MEMINFO = 0x01,
@@ -26,6 +25,7 @@ pub enum Opcode {
     COPY    = 0x08,
     MKDIR   = 0x09,
     RM      = 0x0a,
+    CHECKSUM = 0x0b,
     RESET   = 0xff,
 }
 
@@ -45,129 +45,601 @@ impl TryFrom<u8> for Opcode {
             x if x == Opcode::COPY as u8 => Ok(Opcode::COPY),
             x if x == Opcode::MKDIR as u8 => Ok(Opcode::MKDIR),
             x if x == Opcode::RM as u8 => Ok(Opcode::RM),
+            x if x == Opcode::CHECKSUM as u8 => Ok(Opcode::CHECKSUM),
             x if x == Opcode::RESET as u8 => Ok(Opcode::RESET),
             _ => Err(()),
         }
     }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone)]
+/// Size of a MEMINFO entry: size (u32 LE), flags (u8) and NUL-padded name
+pub const MEMINFO_SIZE: usize = 32;
+
+/// Properties of a memory, encoded in the MEMINFO flags byte
+///
+/// | Bits | Meaning                                                       |
+/// |:----:|:-------------------------------------------------------------:|
+/// | 0    | Read-only                                                     |
+/// | 1    | Erasable                                                      |
+/// | 2    | External to the microcontroller                               |
+/// | 3    | Holds a filesystem                                            |
+/// | 4-7  | Erase block size, 2^(n+8) Bytes, 0 if not applicable          |
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemFlags(pub u8);
+
+impl MemFlags {
+    pub fn read_only(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    pub fn erasable(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    pub fn external(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
+    pub fn filesystem(&self) -> bool {
+        self.0 & 0x08 != 0
+    }
+
+    /// Size in Bytes of the erase block, if the memory has one
+    pub fn erase_block_size(&self) -> Option<u32> {
+        match self.0 >> 4 {
+            0 => None,
+            n => Some(1 << (n + 8)),
+        }
+    }
+}
+
+impl fmt::Display for MemFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut properties = vec![if self.external() { "external" } else { "internal" }];
+        if self.read_only() {
+            properties.push("read-only");
+        }
+        if self.erasable() {
+            properties.push("erasable");
+        }
+        if self.filesystem() {
+            properties.push("filesystem");
+        }
+        write!(f, "{}", properties.join(", "))?;
+        match self.erase_block_size() {
+            Some(size) => write!(f, ", {size}B erase block"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
 pub struct MemInfo {
-    size:  u32,     // Size of the memory in Bytes
-    flags: u8,      // Flags
-    name: [u8; 27], // Name of the memory
+    pub size: u32,          // Size of the memory in Bytes
+    pub flags: MemFlags,
+    pub name: String,       // Name of the memory
 }
 
 // Useful for terminal printing
 impl fmt::Debug for MemInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-               "{} ({}B)",
-               str::from_utf8(&self.name).unwrap(),
-               self.size)
+        write!(f, "{} ({}B)", self.name, self.size)
     }
 }
 
 // Used for deriving file names
 impl std::fmt::Display for MemInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f,
-               "{}_{}",
-               unsafe{ CStr::from_ptr(self.name.as_ptr() as *const i8).to_str()
-                                                                      .unwrap()
-                                                                      .replace(" ", "") },
-               self.size)
+        write!(f, "{}_{}", self.name.replace(' ', ""), self.size)
     }
 }
 
-impl From<&Vec<u8>> for MemInfo {
-    fn from(v: &Vec<u8>) -> MemInfo {
-        unsafe{ *std::mem::transmute::<*const u8, *const MemInfo>(v.as_ptr()) }
+impl TryFrom<&[u8]> for MemInfo {
+    type Error = std::io::Error;
+
+    fn try_from(v: &[u8]) -> Result<Self, Self::Error> {
+        if v.len() != MEMINFO_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                           format!("MEMINFO entry of {} bytes, expected {MEMINFO_SIZE}", v.len())));
+        }
+        let size = u32::from_le_bytes([v[0], v[1], v[2], v[3]]);
+        // The name is NUL-padded, but may fill the whole field
+        let name = &v[5..];
+        let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+        Ok(MemInfo { size, flags: MemFlags(v[4]), name: String::from_utf8_lossy(name).into_owned() })
     }
 }
 
-/// This function sends an FMP command
-pub fn send_cmd(opcode: Opcode, params: Vec<Vec<u8>>) {
-    let mut link = Link::acquire();
-    let mut cmd: Vec<u8> = vec![opcode as u8,
-                                params.len() as u8];
-    for p in params {
-        cmd.push(p.len() as u8);
+/// Region of a memory, in Bytes
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemRange {
+    pub offset: u32,
+    pub length: u32,
+}
+
+impl MemRange {
+    /// Region starting at an offset, by default the start of the memory, and
+    /// spanning the given length, by default up to the end of the memory
+    pub fn new(mem: &MemInfo, offset: Option<u32>, length: Option<u32>) -> std::io::Result<MemRange> {
+        let invalid = |why: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, why);
+        let offset = offset.unwrap_or(0);
+        if offset >= mem.size {
+            return Err(invalid(format!("offset {offset:#x} outside of {mem:?}")));
+        }
+        let length = length.unwrap_or(mem.size - offset);
+        if length == 0 || length > mem.size - offset {
+            return Err(invalid(format!("{length} Bytes from offset {offset:#x} do not fit in {mem:?}")));
+        }
+        Ok(MemRange { offset, length })
+    }
+
+    pub fn whole(mem: &MemInfo) -> MemRange {
+        MemRange { offset: 0, length: mem.size }
+    }
+
+    /// Check that the region can be erased and written without touching
+    /// data outside of it
+    pub fn check_erase_blocks(&self, mem: &MemInfo) -> std::io::Result<()> {
+        match mem.flags.erase_block_size() {
+            Some(block) if !self.offset.is_multiple_of(block) || !self.length.is_multiple_of(block) => {
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                        format!("region {:#x}+{:#x} is not aligned to the {block}B erase blocks of {mem:?}",
+                                                self.offset, self.length)))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for MemRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x}-{:#x}", self.offset, self.offset as u64 + self.length as u64)
+    }
+}
+
+/// Type of an entry of the radio filesystem
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum EntryType {
+    FILE = 0x00,
+    DIR  = 0x01,
+}
+
+/// Entry of a directory of the radio filesystem, as reported by LIST
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: EntryType,
+    pub size: u32,      // Size of the file in Bytes, 0 for directories
+}
+
+impl DirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryType::DIR
+    }
+}
+
+/// Each entry is encoded as type (u8), size (u32 LE) and name
+impl TryFrom<&[u8]> for DirEntry {
+    type Error = std::io::Error;
+
+    fn try_from(v: &[u8]) -> Result<Self, Self::Error> {
+        let invalid = |why: &str| std::io::Error::new(std::io::ErrorKind::InvalidData,
+                                                      format!("invalid LIST entry: {why}"));
+        if v.len() < 6 {
+            return Err(invalid("too short"));
+        }
+        let kind = match v[0] {
+            x if x == EntryType::FILE as u8 => EntryType::FILE,
+            x if x == EntryType::DIR as u8 => EntryType::DIR,
+            x => return Err(invalid(&format!("unknown type {x}"))),
+        };
+        let size = u32::from_le_bytes([v[1], v[2], v[3], v[4]]);
+        let name = str::from_utf8(&v[5..]).map_err(|_| invalid("name is not UTF-8"))?;
+        Ok(DirEntry { name: String::from(name), kind, size })
+    }
+}
+
+/// Join a directory and an entry name into a radio filesystem path
+pub fn join_path(dir: &str, name: &str) -> String {
+    match dir.ends_with('/') {
+        true => format!("{dir}{name}"),
+        false => format!("{dir}/{name}"),
+    }
+}
+
+fn invalid_data(why: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, why)
+}
+
+/// Encode an FMP command: opcode, number of parameters, then the length and
+/// content of each parameter
+pub fn encode(opcode: u8, params: &[Vec<u8>]) -> std::io::Result<Vec<u8>> {
+    let invalid = |why: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, why);
+    let nparams = u8::try_from(params.len()).map_err(|_| invalid(format!("too many FMP parameters: {}", params.len())))?;
+    let mut cmd: Vec<u8> = vec![opcode, nparams];
+    for (i, p) in params.iter().enumerate() {
+        let len = u8::try_from(p.len()).map_err(|_| invalid(format!("FMP parameter {i} too long: {} bytes", p.len())))?;
+        cmd.push(len);
         cmd.extend(p);
-    };
-    let frame = Frame{proto: Protocol::FMP, data: cmd};
-    link.send(frame);
+    }
+    Ok(cmd)
+}
+
+/// FMP reply as received: opcode, status, number of parameters, the length of
+/// each parameter, then the content of each parameter
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Reply {
+    pub opcode: u8,
+    pub status: u8,
+    pub params: Vec<Vec<u8>>,
+}
+
+impl TryFrom<&[u8]> for Reply {
+    type Error = std::io::Error;
+
+    fn try_from(v: &[u8]) -> Result<Self, Self::Error> {
+        let (opcode, status, nparams) = match v {
+            [opcode, status, nparams, ..] => (*opcode, *status, *nparams as usize),
+            _ => return Err(invalid_data(format!("FMP reply of {} bytes is too short", v.len()))),
+        };
+        let sizes = v.get(3..3 + nparams)
+                     .ok_or(invalid_data(format!("FMP reply truncated in the size of {nparams} parameters")))?;
+        let mut offset = 3 + nparams;
+        let mut params = Vec::with_capacity(nparams);
+        for (i, &size) in sizes.iter().enumerate() {
+            let param = v.get(offset..offset + size as usize)
+                         .ok_or(invalid_data(format!("FMP reply truncated in parameter {i}")))?;
+            params.push(param.to_vec());
+            offset += size as usize;
+        }
+        if offset != v.len() {
+            return Err(invalid_data(format!("{} unexpected bytes after FMP reply", v.len() - offset)));
+        }
+        Ok(Reply { opcode, status, params })
+    }
+}
+
+/// FMP command, one per opcode
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Request {
+    MEMINFO,
+    DUMP { mem: u8, range: Option<MemRange> },
+    FLASH { mem: u8, range: Option<MemRange> },
+    READ { path: String },
+    WRITE { path: String, size: u32 },
+    LIST { path: String },
+    MOVE { from: String, to: String },
+    COPY { from: String, to: String },
+    MKDIR { path: String },
+    RM { path: String },
+    CHECKSUM { mem: u8, range: MemRange, algorithm: Algorithm },
+    RESET,
+}
+
+impl Request {
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Request::MEMINFO => Opcode::MEMINFO,
+            Request::DUMP { .. } => Opcode::DUMP,
+            Request::FLASH { .. } => Opcode::FLASH,
+            Request::READ { .. } => Opcode::READ,
+            Request::WRITE { .. } => Opcode::WRITE,
+            Request::LIST { .. } => Opcode::LIST,
+            Request::MOVE { .. } => Opcode::MOVE,
+            Request::COPY { .. } => Opcode::COPY,
+            Request::MKDIR { .. } => Opcode::MKDIR,
+            Request::RM { .. } => Opcode::RM,
+            Request::CHECKSUM { .. } => Opcode::CHECKSUM,
+            Request::RESET => Opcode::RESET,
+        }
+    }
+
+    fn params(&self) -> Vec<Vec<u8>> {
+        let text = |s: &String| s.as_bytes().to_vec();
+        match self {
+            Request::MEMINFO | Request::RESET => vec![],
+            // Whole memories are requested with the index only
+            Request::DUMP { mem, range: None } | Request::FLASH { mem, range: None } => vec![vec![*mem]],
+            Request::DUMP { mem, range: Some(range) } | Request::FLASH { mem, range: Some(range) } => {
+                vec![vec![*mem], range.offset.to_le_bytes().to_vec(), range.length.to_le_bytes().to_vec()]
+            },
+            Request::READ { path } | Request::LIST { path } |
+            Request::MKDIR { path } | Request::RM { path } => vec![text(path)],
+            Request::WRITE { path, size } => vec![text(path), size.to_le_bytes().to_vec()],
+            Request::MOVE { from, to } | Request::COPY { from, to } => vec![text(from), text(to)],
+            Request::CHECKSUM { mem, range, algorithm } => {
+                vec![vec![*mem], range.offset.to_le_bytes().to_vec(), range.length.to_le_bytes().to_vec(),
+                     vec![*algorithm as u8]]
+            },
+        }
+    }
+
+    /// Binary representation of the command, failing if a parameter does not fit
+    pub fn encode(&self) -> std::io::Result<Vec<u8>> {
+        encode(self.opcode() as u8, &self.params())
+    }
+}
+
+/// Successful FMP reply, one per opcode. Parameters of replies carrying no
+/// data are ignored
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Response {
+    MEMINFO(Vec<MemInfo>),
+    DUMP,
+    FLASH,
+    READ { size: u32 },
+    WRITE,
+    LIST(Vec<DirEntry>),
+    MOVE,
+    COPY,
+    MKDIR,
+    RM,
+    CHECKSUM(Vec<u8>),
+    RESET,
+}
+
+impl Response {
+    /// Decode the reply to a command, returning the error reported by the
+    /// radio if the status is not OK
+    pub fn decode(opcode: Opcode, data: &[u8]) -> std::io::Result<Response> {
+        let reply = Reply::try_from(data)?;
+        if reply.opcode != opcode as u8 {
+            return Err(invalid_data(format!("FMP reply opcode {:#04x}, expected {:?}", reply.opcode, opcode)));
+        }
+        if reply.status != Errno::OK as u8 {
+            return Err(Errno::error(reply.status));
+        }
+        let response = match opcode {
+            Opcode::MEMINFO => Response::MEMINFO(reply.params.iter()
+                                                              .map(|m| MemInfo::try_from(m.as_slice()))
+                                                              .collect::<std::io::Result<_>>()?),
+            Opcode::DUMP => Response::DUMP,
+            Opcode::FLASH => Response::FLASH,
+            Opcode::READ => match reply.params.first().map(|p| p.as_slice()) {
+                Some(&[a, b, c, d]) => Response::READ { size: u32::from_le_bytes([a, b, c, d]) },
+                _ => return Err(invalid_data(String::from("missing file size in READ reply"))),
+            },
+            Opcode::WRITE => Response::WRITE,
+            Opcode::LIST => Response::LIST(reply.params.iter()
+                                                       .map(|e| DirEntry::try_from(e.as_slice()))
+                                                       .collect::<std::io::Result<_>>()?),
+            Opcode::MOVE => Response::MOVE,
+            Opcode::COPY => Response::COPY,
+            Opcode::MKDIR => Response::MKDIR,
+            Opcode::RM => Response::RM,
+            Opcode::CHECKSUM => match reply.params.first() {
+                Some(digest) => Response::CHECKSUM(digest.clone()),
+                None => return Err(invalid_data(String::from("missing digest in CHECKSUM reply"))),
+            },
+            Opcode::RESET => Response::RESET,
+            Opcode::ACK => return Err(invalid_data(String::from("ACK is not a reply to a command"))),
+        };
+        Ok(response)
+    }
+}
+
+/// This function sends an FMP command with an arbitrary opcode
+pub fn send_raw(opcode: u8, params: &[Vec<u8>]) -> std::io::Result<()> {
+    let cmd = encode(opcode, params)?;
+    let mut link = Link::acquire();
+    link.send(Frame{proto: Protocol::FMP, data: cmd});
     link.release();
+    Ok(())
 }
 
-/// This function reads the response of an FMP command, checking the error code
-/// and returning the arguments of the response, possibly none
-pub fn wait_reply(opcode: Opcode) -> Vec<Vec<u8>> {
+/// This function waits for the next FMP frame and returns its content
+fn receive() -> std::io::Result<Vec<u8>> {
     let mut link = Link::acquire();
     // Loop until we get a message of the right protocol
-    let mut frame: Frame;
-    loop {
-        frame = link.receive().expect("Error while reading frame");
-        match frame.proto {
-            Protocol::FMP => break,
-            _ => (),
-        };
+    let frame = loop {
+        match link.receive() {
+            Ok(Frame { proto: Protocol::FMP, data }) => break data,
+            Ok(_) => (),
+            Err(kind) => { link.release(); return Err(std::io::Error::from(kind)) },
+        }
+    };
+    link.release();
+    Ok(frame)
+}
+
+/// This function reads the response of an FMP command with an arbitrary opcode,
+/// checking the error code and returning the parameters of the response
+pub fn wait_reply_raw(opcode: u8) -> std::io::Result<Vec<Vec<u8>>> {
+    let reply = Reply::try_from(receive()?.as_slice())?;
+    if reply.opcode != opcode {
+        return Err(invalid_data(format!("FMP reply opcode {:#04x}, expected {opcode:#04x}", reply.opcode)));
     }
-    let rx_opcode = Opcode::try_from(frame.data[0]).expect("Opcode not implemented!");
-    if rx_opcode != opcode {
-        eprintln!("{}: mismatched opcode in FMP response!", "Error".bold().red());
-        return vec![]
-    }
-    // Parse status byte
-    let status = frame.data[1];
-    match status {
-        0 => (),
-        status => println!("{}: {:?}", "Error".bold().red(), Errno::try_from(status).unwrap()),
-    }
-    // Extract parameters
-    let nparams = frame.data[2] as usize;
-    let mut params = Vec::new();
-    let mut prev_params: usize = 0;
-    for _i in 0..nparams {
-        // Keep track of the offset
-        let param_size: usize = frame.data[3 + _i] as usize;
-        let mut param = Vec::with_capacity(param_size);
-        // Skip FMP header, param sizes and previous params
-        for j in 3 + nparams + prev_params..3 + nparams + prev_params + param_size {
-            param.push(frame.data[j]);
-        }
-        params.push(param);
-        prev_params += param_size;
+    match reply.status {
+        0 => Ok(reply.params),
+        status => Err(Errno::error(status)),
     }
+}
+
+/// This function sends an FMP command and waits for its response
+pub fn transact(request: &Request) -> std::io::Result<Response> {
+    let cmd = request.encode()?;
+    let mut link = Link::acquire();
+    link.send(Frame{proto: Protocol::FMP, data: cmd});
     link.release();
-    params
+    Response::decode(request.opcode(), &receive()?)
+}
+
+/// Error for a response not matching the command, which decoding rules out
+fn unexpected(response: Response) -> std::io::Error {
+    invalid_data(format!("unexpected FMP response {response:?}"))
+}
+
+/// Memory index as sent in FMP commands
+fn mem_index(mem_id: usize) -> std::io::Result<u8> {
+    u8::try_from(mem_id).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                                         format!("invalid memory index {mem_id}")))
 }
 
 /// Print info about the memories available on the platform
-pub fn meminfo() -> Vec<MemInfo> {
-    send_cmd(Opcode::MEMINFO, vec![]);
-    // Receive MEMINFO response
-    let available_mem = wait_reply(Opcode::MEMINFO);
-    // Return MEMINFO response
-    let mem_list = available_mem.iter()
-                                .map(|m| MemInfo::from(m))
-                                .collect();
-    mem_list
+pub fn meminfo() -> std::io::Result<Vec<MemInfo>> {
+    match transact(&Request::MEMINFO)? {
+        Response::MEMINFO(mem_list) => Ok(mem_list),
+        response => Err(unexpected(response)),
+    }
+}
+
+/// List the content of a directory of the radio filesystem
+pub fn list(path: &str) -> std::io::Result<Vec<DirEntry>> {
+    match transact(&Request::LIST { path: String::from(path) })? {
+        Response::LIST(entries) => Ok(entries),
+        response => Err(unexpected(response)),
+    }
 }
 
 /// Dump memory device into a file
-pub fn dump(mem_id: usize, mem: &MemInfo, file_name: &str, progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<()> {
-    // Send Dump FMP command then listen for incoming DAT transfer
-    send_cmd(Opcode::DUMP, [[mem_id as u8].to_vec()].to_vec());
-    wait_reply(Opcode::DUMP);
-    dat::receive(file_name, mem.size as usize, progress)
-}
-
-/// Flash a given file into a particular memory device of a radio
-pub fn flash(mem_id: usize, mem: &MemInfo, file_name: &str, progress: Option<&Sender<(usize, usize)>>) {
-    // Send Fump FMP command then send content over DAT
-    send_cmd(Opcode::FLASH, [[mem_id as u8].to_vec()].to_vec());
-    wait_reply(Opcode::FLASH);
-    dat::send(file_name, mem.size as usize, progress);
+pub fn dump(mem_id: usize, mem: &MemInfo, file_name: &str, range: Option<MemRange>,
+            progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<()> {
+    let size = request_dump(mem_id, mem, range)?;
+    dat::receive(file_name, size, progress)
+}
+
+/// Read a memory device, or a region of it, into memory
+pub fn read(mem_id: usize, mem: &MemInfo, range: Option<MemRange>,
+            progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<Vec<u8>> {
+    let size = request_dump(mem_id, mem, range)?;
+    dat::receive_data(size, progress)
+}
+
+/// Send the DUMP command, returning the size of the incoming DAT transfer
+fn request_dump(mem_id: usize, mem: &MemInfo, range: Option<MemRange>) -> std::io::Result<usize> {
+    let range = range.filter(|r| *r != MemRange::whole(mem));
+    let size = range.map(|r| r.length).unwrap_or(mem.size);
+    // Send Dump FMP command, the DAT transfer follows
+    transact(&Request::DUMP { mem: mem_index(mem_id)?, range })?;
+    Ok(size as usize)
+}
+
+/// Download a file from the radio filesystem, its size is reported by the
+/// radio in the READ reply
+pub fn read_file(remote: &str, local: &str, progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<()> {
+    match transact(&Request::READ { path: String::from(remote) })? {
+        Response::READ { size } => dat::receive(local, size as usize, progress),
+        response => Err(unexpected(response)),
+    }
+}
+
+/// Upload a file to the radio filesystem, the radio refuses it if there is
+/// not enough space or the filesystem is read-only
+pub fn write_file(local: &str, remote: &str, progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<()> {
+    let size = std::fs::metadata(local)?.len();
+    let size = u32::try_from(size).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                                                   "file too large for the radio"))?;
+    let data = std::fs::read(local)?;
+    if data.len() != size as usize {
+        return Err(std::io::Error::other(format!("{local} changed while uploading")));
+    }
+    transact(&Request::WRITE { path: String::from(remote), size })?;
+    dat::send(&data, progress)
+}
+
+/// Move or rename a file or directory of the radio filesystem
+pub fn rename(from: &str, to: &str) -> std::io::Result<()> {
+    transact(&Request::MOVE { from: String::from(from), to: String::from(to) }).map(|_| ())
+}
+
+/// Copy a file of the radio filesystem, the copy is made on the radio
+pub fn copy(from: &str, to: &str) -> std::io::Result<()> {
+    transact(&Request::COPY { from: String::from(from), to: String::from(to) }).map(|_| ())
+}
+
+/// Create a directory of the radio filesystem
+pub fn mkdir(path: &str) -> std::io::Result<()> {
+    transact(&Request::MKDIR { path: String::from(path) }).map(|_| ())
+}
+
+/// Create a directory and all its missing parents, existing ones are fine
+pub fn mkdir_all(path: &str) -> std::io::Result<()> {
+    for prefix in path_prefixes(path) {
+        match mkdir(&prefix) {
+            Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => return Err(e),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Paths of the directories leading to a path, from the root, e.g. /a and
+/// /a/b for a/b/
+pub(crate) fn path_prefixes(path: &str) -> Vec<String> {
+    let mut prefixes: Vec<String> = Vec::new();
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        let parent = prefixes.last().map(String::as_str).unwrap_or("/");
+        prefixes.push(join_path(parent, component));
+    }
+    prefixes
+}
+
+/// Remove a file or an empty directory of the radio filesystem
+pub fn remove(path: &str) -> std::io::Result<()> {
+    transact(&Request::RM { path: String::from(path) }).map(|_| ())
+}
+
+/// Remove a directory and all its content
+pub fn remove_all(path: &str) -> std::io::Result<()> {
+    remove_tree(path, &list, &mut remove)
+}
+
+/// Remove a directory tree depth first, listing and removing its entries
+/// with the given functions
+pub(crate) fn remove_tree(path: &str, list: &impl Fn(&str) -> std::io::Result<Vec<DirEntry>>,
+                          remove: &mut impl FnMut(&str) -> std::io::Result<()>) -> std::io::Result<()> {
+    for entry in list(path)? {
+        let child = join_path(path, &entry.name);
+        match entry.is_dir() {
+            true => remove_tree(&child, list, remove)?,
+            false => remove(&child)?,
+        }
+    }
+    remove(path)
+}
+
+/// Flash data into a particular memory device of a radio, either the whole
+/// memory or the given region of it
+pub fn flash(mem_id: usize, mem: &MemInfo, data: &[u8], range: Option<MemRange>,
+             progress: Option<&Sender<(usize, usize)>>) -> std::io::Result<()> {
+    let range = range.filter(|r| *r != MemRange::whole(mem));
+    let size = range.map(|r| r.length).unwrap_or(mem.size);
+    if data.len() != size as usize {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                                       format!("{} Bytes of data do not match the {size} Bytes to flash", data.len())));
+    }
+    if let Some(range) = range {
+        range.check_erase_blocks(mem)?;
+    }
+    // Send Flash FMP command then send content over DAT
+    transact(&Request::FLASH { mem: mem_index(mem_id)?, range })?;
+    dat::send(data, progress)
+}
+
+/// Ask the radio to compute the checksum of a memory, or of a region of it,
+/// without transferring its content. Firmware without this command reports
+/// an error of kind Unsupported
+pub fn checksum(mem_id: usize, mem: &MemInfo, range: Option<MemRange>, algorithm: Algorithm) -> std::io::Result<Vec<u8>> {
+    let range = range.unwrap_or(MemRange::whole(mem));
+    match transact(&Request::CHECKSUM { mem: mem_index(mem_id)?, range, algorithm })? {
+        Response::CHECKSUM(digest) if digest.len() == algorithm.digest_size() => Ok(digest),
+        Response::CHECKSUM(digest) => {
+            Err(invalid_data(format!("{algorithm:?} digest of {} bytes, expected {}", digest.len(), algorithm.digest_size())))
+        },
+        response => Err(unexpected(response)),
+    }
+}
+
+/// Outcome of a reset request: the radio may reboot before acknowledging,
+/// so a missing reply is not an error
+pub(crate) fn reset_outcome<T>(result: std::io::Result<T>) -> std::io::Result<()> {
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+/// Reset the radio, which also makes it leave file transfer mode
+pub fn reset() -> std::io::Result<()> {
+    reset_outcome(transact(&Request::RESET))
 }
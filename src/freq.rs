@@ -0,0 +1,165 @@
+//! This module handles the representation of radio frequencies
+
+/*!
+Frequencies are stored as an exact number of Hertz and parsed from decimal
+strings without going through floating point, so that a value like
+`430.0125` always maps to `430012500` Hz.
+
+The accepted input formats are the following:
+
+```text
+|     Input     |      Meaning       |
+|:-------------:|:------------------:|
+| 430.0125      | MHz (default)      |
+| 430.0125 MHz  | MHz                |
+| 145500k       | kHz                |
+| 145.500.000   | Hz, grouped digits |
+| 1.2975G       | GHz                |
+| 433920000Hz   | Hz                 |
+```
+
+Units are case insensitive and can be abbreviated to their first letter,
+except for Hertz which must be written as `Hz`.
+*/
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Radio frequency, in Hertz
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Frequency(u32);
+
+/// Errors that can occur while parsing a frequency
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseFrequencyError {
+    Empty,               // No digits in the input
+    InvalidDigit,        // Unexpected character in the numeric part
+    InvalidUnit(String), // Unknown unit suffix
+    InvalidGrouping,     // Digit groups not made of three digits
+    TooPrecise,          // Value has a fractional number of Hertz
+    OutOfRange,          // Value does not fit in 32 bits
+}
+
+impl fmt::Display for ParseFrequencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseFrequencyError::Empty => write!(f, "empty frequency"),
+            ParseFrequencyError::InvalidDigit => write!(f, "invalid digit in frequency"),
+            ParseFrequencyError::InvalidUnit(u) => write!(f, "unknown frequency unit \"{u}\""),
+            ParseFrequencyError::InvalidGrouping => write!(f, "digit groups must be made of three digits"),
+            ParseFrequencyError::TooPrecise => write!(f, "frequency has a fractional number of Hz"),
+            ParseFrequencyError::OutOfRange => write!(f, "frequency out of range"),
+        }
+    }
+}
+
+impl std::error::Error for ParseFrequencyError {}
+
+/// Number of decimal digits of a Hz value below each unit
+fn unit_digits(unit: &str) -> Result<Option<u32>, ParseFrequencyError> {
+    match unit.to_ascii_lowercase().as_str() {
+        "" => Ok(None),
+        "hz" => Ok(Some(0)),
+        "k" | "khz" => Ok(Some(3)),
+        "m" | "mhz" => Ok(Some(6)),
+        "g" | "ghz" => Ok(Some(9)),
+        _ => Err(ParseFrequencyError::InvalidUnit(String::from(unit))),
+    }
+}
+
+/// Parse a string made only of decimal digits
+fn parse_digits(digits: &str) -> Result<u64, ParseFrequencyError> {
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseFrequencyError::InvalidDigit);
+    }
+    digits.bytes().try_fold(0u64, |acc, b| {
+        acc.checked_mul(10)
+           .and_then(|acc| acc.checked_add((b - b'0') as u64))
+           .ok_or(ParseFrequencyError::OutOfRange)
+    })
+}
+
+impl Frequency {
+    pub const fn from_hz(hz: u32) -> Frequency {
+        Frequency(hz)
+    }
+
+    pub const fn hz(self) -> u32 {
+        self.0
+    }
+
+    /// Add a signed offset in Hertz, returning None on overflow
+    pub fn offset(self, offset: i64) -> Option<Frequency> {
+        let hz = (self.0 as i64).checked_add(offset)?;
+        u32::try_from(hz).ok().map(Frequency)
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = ParseFrequencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // Split numeric part and unit suffix
+        let unit_start = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(unit_start);
+        let number: String = number.trim_end().chars().filter(|&c| c != '_').collect();
+        let unit = unit_digits(unit)?;
+        if !number.bytes().any(|b| b.is_ascii_digit()) {
+            return Err(ParseFrequencyError::Empty);
+        }
+
+        let groups: Vec<&str> = number.split('.').collect();
+        let (integer, fraction, digits) = if groups.len() > 2 {
+            // Dots used as thousands separators, e.g. 145.500.000
+            if groups[0].is_empty() || groups[1..].iter().any(|g| g.len() != 3) {
+                return Err(ParseFrequencyError::InvalidGrouping);
+            }
+            (groups.concat(), String::new(), unit.unwrap_or(0))
+        } else {
+            let fraction = groups.get(1).copied().unwrap_or("");
+            (String::from(groups[0]), String::from(fraction), unit.unwrap_or(6))
+        };
+
+        // Fractional digits below 1Hz are accepted only if they are zeros
+        let (fraction, sub_hz) = fraction.split_at(fraction.len().min(digits as usize));
+        if parse_digits(sub_hz)? != 0 {
+            return Err(ParseFrequencyError::TooPrecise);
+        }
+        let scale = 10u64.pow(digits);
+        let fraction_scale = 10u64.pow(digits - fraction.len() as u32);
+        let fraction = parse_digits(fraction)? * fraction_scale;
+        let hz = parse_digits(&integer)?
+            .checked_mul(scale)
+            .and_then(|hz| hz.checked_add(fraction))
+            .ok_or(ParseFrequencyError::OutOfRange)?;
+        u32::try_from(hz).map(Frequency)
+                         .map_err(|_| ParseFrequencyError::OutOfRange)
+    }
+}
+
+/// Parse a signed frequency offset such as a repeater shift, e.g. +600k or
+/// -7.6M, offsets without sign are positive
+pub fn parse_offset(s: &str) -> Result<i64, ParseFrequencyError> {
+    let s = s.trim();
+    let (sign, magnitude) = match s.strip_prefix('-') {
+        Some(magnitude) => (-1, magnitude),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let magnitude: Frequency = magnitude.parse()?;
+    Ok(sign * magnitude.hz() as i64)
+}
+
+// Canonical representation, in MHz without trailing zeros
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mhz = self.0 / 1_000_000;
+        let fraction = format!("{:06}", self.0 % 1_000_000);
+        let fraction = fraction.trim_end_matches('0');
+        if fraction.is_empty() {
+            write!(f, "{mhz} MHz")
+        } else {
+            write!(f, "{mhz}.{fraction} MHz")
+        }
+    }
+}
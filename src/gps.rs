@@ -0,0 +1,121 @@
+//! This module handles the GPS position reported by the radio
+
+/*!
+## GPS Data Format
+
+The reply to a CAT GET GPS request has the following layout, multi-byte
+fields are little endian:
+
+```text
+| Offset | Size |     Field      |              Unit              |
+|:------:|:----:|:--------------:|:------------------------------:|
+|   0    |  1   | Fix quality    | 0: invalid, 1: GPS, 2: DGPS    |
+|   1    |  1   | Fix type       | 1: none, 2: 2D, 3: 3D          |
+|   2    |  1   | Satellites     | tracked satellites             |
+|   3    |  1   | In view        | satellites in view             |
+|   4    |  4   | Latitude       | f32, degrees, positive North   |
+|   8    |  4   | Longitude      | f32, degrees, positive East    |
+|   12   |  4   | Altitude       | f32, meters above sea level    |
+|   16   |  4   | Speed          | f32, km/h                      |
+|   20   |  4   | Course         | f32, degrees from true North   |
+```
+*/
+
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+
+/// Size of the GPS data sent by the radio
+const GPS_DATA_SIZE: usize = 24;
+
+/// Kilometers per hour in a knot
+const KMH_IN_KNOT: f64 = 1.852;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GpsInfo {
+    pub fix_quality: u8,        // 0: invalid, 1: GPS, 2: DGPS
+    pub fix_type: u8,           // 1: none, 2: 2D, 3: 3D
+    pub satellites: u8,         // Satellites used for the fix
+    pub satellites_in_view: u8, // Satellites in view
+    pub latitude: f32,          // Degrees, positive North
+    pub longitude: f32,         // Degrees, positive East
+    pub altitude: f32,          // Meters above sea level
+    pub speed: f32,             // km/h
+    pub course: f32,            // Degrees from true North
+}
+
+/// Append the NMEA checksum and line terminator to a sentence body
+fn nmea_sentence(body: &str) -> String {
+    let checksum = body.bytes().fold(0u8, |acc, b| acc ^ b);
+    format!("${body}*{checksum:02X}\r\n")
+}
+
+/// Format a coordinate as NMEA degrees and decimal minutes with hemisphere.
+/// The value is rounded to 1e-4 minutes before being split, so that minutes
+/// rounding up to 60 carry into the degrees
+pub(crate) fn nmea_coordinate(value: f32, deg_digits: usize, hemispheres: (char, char)) -> String {
+    let value = value as f64;
+    let hemisphere = if value < 0.0 { hemispheres.1 } else { hemispheres.0 };
+    let total = (value.abs() * 60.0 * 10_000.0).round() as u64;
+    let (degrees, minutes) = (total / 600_000, total % 600_000);
+    format!("{:0width$}{:02}.{:04},{}", degrees, minutes / 10_000, minutes % 10_000, hemisphere, width = deg_digits)
+}
+
+impl GpsInfo {
+    /// Parse the GPS data sent by the radio
+    pub fn from_bytes(data: &[u8]) -> Result<GpsInfo> {
+        if data.len() < GPS_DATA_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("GPS data too short: {} bytes", data.len())));
+        }
+        Ok(GpsInfo {
+            fix_quality: data[0],
+            fix_type: data[1],
+            satellites: data[2],
+            satellites_in_view: data[3],
+            latitude: LittleEndian::read_f32(&data[4..8]),
+            longitude: LittleEndian::read_f32(&data[8..12]),
+            altitude: LittleEndian::read_f32(&data[12..16]),
+            speed: LittleEndian::read_f32(&data[16..20]),
+            course: LittleEndian::read_f32(&data[20..24]),
+        })
+    }
+
+    pub fn has_fix(&self) -> bool {
+        self.fix_quality != 0
+    }
+
+    /// Generate the GGA and RMC NMEA sentences describing this fix
+    pub fn nmea(&self, time: DateTime<Utc>) -> Vec<String> {
+        let utc = time.format("%H%M%S%.3f").to_string();
+        let date = time.format("%d%m%y").to_string();
+        let lat = nmea_coordinate(self.latitude, 2, ('N', 'S'));
+        let lon = nmea_coordinate(self.longitude, 3, ('E', 'W'));
+        let status = if self.has_fix() { 'A' } else { 'V' };
+        let knots = self.speed as f64 / KMH_IN_KNOT;
+        let gga = format!("GPGGA,{utc},{lat},{lon},{},{:02},,{:.1},M,,M,,",
+                          self.fix_quality, self.satellites, self.altitude);
+        let rmc = format!("GPRMC,{utc},{status},{lat},{lon},{knots:.1},{:.1},{date},,,{}",
+                          self.course, if self.has_fix() { 'A' } else { 'N' });
+        vec![nmea_sentence(&gga), nmea_sentence(&rmc)]
+    }
+}
+
+impl fmt::Display for GpsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fix = match (self.fix_quality, self.fix_type) {
+            (0, _) => "no fix",
+            (_, 2) => "2D fix",
+            (_, 3) => "3D fix",
+            _ => "fix",
+        };
+        writeln!(f, "Fix: {fix}{}", if self.fix_quality == 2 { " (DGPS)" } else { "" })?;
+        writeln!(f, "Satellites: {} in use, {} in view", self.satellites, self.satellites_in_view)?;
+        writeln!(f, "Latitude: {:.6}", self.latitude)?;
+        writeln!(f, "Longitude: {:.6}", self.longitude)?;
+        writeln!(f, "Altitude: {:.1} m", self.altitude)?;
+        writeln!(f, "Speed: {:.1} km/h", self.speed)?;
+        write!(f, "Course: {:.1}°", self.course)
+    }
+}
@@ -0,0 +1,37 @@
+//! This module handles the hexadecimal representation of raw data
+
+/// Parse a string of hexadecimal digits, optionally prefixed by 0x and with
+/// bytes separated by spaces, colons or dashes
+pub fn parse(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.strip_prefix("0x").unwrap_or(text);
+    let digits: Vec<char> = text.chars()
+                                .filter(|c| !matches!(c, ' ' | ':' | '-'))
+                                .collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("odd number of hex digits in \"{text}\""));
+    }
+    digits.chunks(2)
+          .map(|pair| {
+              let byte: String = pair.iter().collect();
+              u8::from_str_radix(&byte, 16).map_err(|_| format!("invalid hex byte \"{byte}\""))
+          })
+          .collect()
+}
+
+/// Format data as a string of lowercase hex digits
+pub fn encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Format data as lines of offset, 16 hex bytes and their printable characters
+pub fn dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{b:02x}")).collect();
+        let ascii: String = line.iter()
+                                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                                .collect();
+        out.push_str(&format!("{:08x}  {:<47}  |{}|\n", i * 16, hex.join(" "), ascii));
+    }
+    out
+}
@@ -0,0 +1,140 @@
+//! This module handles the identification of the radio from the CAT INFO reply
+
+/*!
+The stock firmware replies to CAT INFO with the bare hardware name, e.g.
+`MD-UV3x0`, newer versions may append further fields. Fields can either be
+tagged with a key or be recognized by their shape:
+
+```text
+| Key                    | Field             | Untagged example     |
+|:----------------------:|:-----------------:|:--------------------:|
+| model, name            | Model             | MD-UV3x0             |
+| hw, hwrev, rev         | Hardware revision | rev1.2               |
+| fw, firmware, version  | Firmware version  | v0.3.6-42-gabc1234   |
+| git, hash              | Git hash          | (from -g suffix)     |
+| sn, serial             | Serial number     |                      |
+```
+
+Keys are separated from their value by `=` or `:`, fields by whitespace,
+commas or semicolons. Replies which cannot be parsed are kept as the model
+name so that the radio can always be identified.
+*/
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RadioInfo {
+    pub model:    String,         // Radio model
+    pub hw_rev:   Option<String>, // Hardware revision
+    pub firmware: Option<String>, // Firmware version
+    pub git_hash: Option<String>, // Git hash of the firmware build
+    pub serial:   Option<String>, // Serial number
+    pub raw:      String,         // Unparsed INFO reply
+}
+
+/// Check whether a token looks like a version number, e.g. v0.3.6 or 0.3
+fn is_version(token: &str) -> bool {
+    let version = token.strip_prefix(['v', 'V']).unwrap_or(token);
+    let mut parts = version.split('.');
+    parts.next().is_some_and(|p| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()))
+        && parts.next().is_some_and(|p| p.bytes().next().is_some_and(|b| b.is_ascii_digit()))
+}
+
+/// Extract the hardware revision from a token such as rev0.1e, rev.2 or rev-1,
+/// which must have a digit after the prefix so that words like Revo are not
+fn revision(token: &str) -> Option<&str> {
+    if !token.get(..3)?.eq_ignore_ascii_case("rev") {
+        return None;
+    }
+    let rev = token[3..].strip_prefix(['.', '-']).unwrap_or(&token[3..]);
+    rev.bytes().next().filter(u8::is_ascii_digit).map(|_| rev)
+}
+
+/// Extract the git hash from a `git describe` version, e.g. v0.3.6-42-gabc1234
+/// or v0.3.6-42-gabc1234-dirty
+fn describe_hash(version: &str) -> Option<String> {
+    let version = version.strip_suffix("-dirty").unwrap_or(version);
+    let hash = version.rsplit('-').next()?.strip_prefix('g')?;
+    match hash.len() >= 7 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        true => Some(String::from(hash)),
+        false => None,
+    }
+}
+
+impl RadioInfo {
+    /// Parse the reply to a CAT INFO request
+    pub fn parse(raw: &str) -> RadioInfo {
+        let raw = raw.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        let mut model = Vec::new();
+        let mut info = RadioInfo {
+            model: String::new(),
+            hw_rev: None,
+            firmware: None,
+            git_hash: None,
+            serial: None,
+            raw: String::from(raw),
+        };
+
+        for token in raw.split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+                        .filter(|t| !t.is_empty()) {
+            let field = token.split_once(['=', ':'])
+                             .map(|(k, v)| (k.to_ascii_lowercase(), String::from(v)));
+            match field {
+                Some((key, value)) => match key.as_str() {
+                    "model" | "name" => model.push(value),
+                    "hw" | "hwrev" | "rev" => info.hw_rev = Some(value),
+                    "fw" | "firmware" | "version" => info.firmware = Some(value),
+                    "git" | "hash" => info.git_hash = Some(value),
+                    "sn" | "serial" => info.serial = Some(value),
+                    // Unknown keys are part of an unknown format, keep them
+                    _ => model.push(String::from(token)),
+                },
+                None if token.eq_ignore_ascii_case("openrtx") => (),
+                None if info.firmware.is_none() && is_version(token) => {
+                    info.firmware = Some(String::from(token))
+                },
+                None if info.hw_rev.is_none() && revision(token).is_some() => {
+                    info.hw_rev = revision(token).map(String::from)
+                },
+                None => model.push(String::from(token)),
+            }
+        }
+
+        if info.git_hash.is_none() {
+            info.git_hash = info.firmware.as_deref().and_then(describe_hash);
+        }
+        info.model = match model.is_empty() {
+            true if raw.is_empty() => String::from("unknown"),
+            true => String::from(raw),
+            false => model.join(" "),
+        };
+        info
+    }
+
+    /// Identifier of the radio suitable for file names
+    pub fn id(&self) -> String {
+        let mut id: String = self.model.chars()
+                                       .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+                                       .collect();
+        if let Some(serial) = &self.serial {
+            id.push('-');
+            id.extend(serial.chars().filter(|c| c.is_ascii_alphanumeric()));
+        }
+        id
+    }
+}
+
+impl fmt::Display for RadioInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.model)?;
+        let fields = [("hw", &self.hw_rev), ("fw", &self.firmware),
+                      ("git", &self.git_hash), ("sn", &self.serial)];
+        let fields: Vec<String> = fields.iter()
+                                        .filter_map(|(k, v)| v.as_ref().map(|v| format!("{k} {v}")))
+                                        .collect();
+        if !fields.is_empty() {
+            write!(f, " ({})", fields.join(", "))?;
+        }
+        Ok(())
+    }
+}
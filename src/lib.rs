@@ -1,7 +1,19 @@
+pub mod archive;
+pub mod bandplan;
 pub mod cat;
+pub mod checksum;
 pub mod dat;
 pub mod flow;
 pub mod fmp;
+pub mod freq;
+pub mod gps;
+pub mod hex;
+pub mod info;
 pub mod link;
+pub mod monitor;
+pub mod script;
+pub mod shell;
 pub mod slip;
+pub mod sync;
+pub mod term;
 mod tests;
@@ -3,7 +3,7 @@
 /*!
 ## Frame Format
 
-```
+```text
 |  0  |    1    |  ... |  N-1 |  N  |
 |:---:|:-------:|:----:|:----:|:---:|
 | END | ProtoID | Data | CRC8 | END |
@@ -13,7 +13,7 @@ Following the leading END marker, the first byte of each frame is a protocol ide
 
 The recognized protocol IDs are the following:
 
-```
+```text
 |  ID  |    Frame content     |
 |:----:|:--------------------:|
 | 0x00 | stdio redirection    |
@@ -28,8 +28,10 @@ use serialport::SerialPort;
 use std::convert::TryFrom;
 use std::collections::VecDeque;
 use std::time::Duration;
+use std::fmt;
 use std::io;
 use std::mem::replace;
+use std::sync::Mutex;
 
 use crate::slip;
 
@@ -45,7 +47,17 @@ pub enum Protocol {
 #[derive(Debug)]
 pub enum Errno {
     OK      = 0,    // Success
+    ENOENT  = 2,    // No such file or directory
+    EIO     = 5,    // I/O error
     E2BIG   = 7,    // Argument list too long
+    EACCES  = 13,   // Permission denied
+    EEXIST  = 17,   // File exists
+    ENOTDIR = 20,   // Not a directory
+    EISDIR  = 21,   // Is a directory
+    EINVAL  = 22,   // Invalid argument
+    ENOSPC  = 28,   // No space left on device
+    EROFS   = 30,   // Read-only file system
+    ENOTEMPTY = 39, // Directory not empty
     EBADR   = 53,   // Invalid request descriptor
     EBADRQC = 56,   // Invalid request code
     EGENERIC = 255, // Generic error
@@ -57,7 +69,17 @@ impl TryFrom<u8> for Errno {
     fn try_from(v: u8) -> Result<Self, Self::Error> {
         match v {
             x if x == Errno::OK as u8 => Ok(Errno::OK),
+            x if x == Errno::ENOENT as u8 => Ok(Errno::ENOENT),
+            x if x == Errno::EIO as u8 => Ok(Errno::EIO),
             x if x == Errno::E2BIG as u8 => Ok(Errno::E2BIG),
+            x if x == Errno::EACCES as u8 => Ok(Errno::EACCES),
+            x if x == Errno::EEXIST as u8 => Ok(Errno::EEXIST),
+            x if x == Errno::ENOTDIR as u8 => Ok(Errno::ENOTDIR),
+            x if x == Errno::EISDIR as u8 => Ok(Errno::EISDIR),
+            x if x == Errno::EINVAL as u8 => Ok(Errno::EINVAL),
+            x if x == Errno::ENOSPC as u8 => Ok(Errno::ENOSPC),
+            x if x == Errno::EROFS as u8 => Ok(Errno::EROFS),
+            x if x == Errno::ENOTEMPTY as u8 => Ok(Errno::ENOTEMPTY),
             x if x == Errno::EBADR as u8 => Ok(Errno::EBADR),
             x if x == Errno::EBADRQC as u8 => Ok(Errno::EBADRQC),
             x if x == Errno::EGENERIC as u8 => Ok(Errno::EGENERIC),
@@ -66,6 +88,43 @@ impl TryFrom<u8> for Errno {
     }
 }
 
+impl Errno {
+    /// Kind of I/O error and description corresponding to an error code
+    pub(crate) fn describe(&self) -> (io::ErrorKind, &'static str) {
+        match self {
+            Errno::OK => (io::ErrorKind::Other, "success"),
+            Errno::ENOENT => (io::ErrorKind::NotFound, "no such file or directory"),
+            Errno::EIO => (io::ErrorKind::Other, "I/O error"),
+            Errno::E2BIG => (io::ErrorKind::InvalidInput, "argument list too long"),
+            Errno::EACCES => (io::ErrorKind::PermissionDenied, "permission denied"),
+            Errno::EEXIST => (io::ErrorKind::AlreadyExists, "file exists"),
+            Errno::ENOTDIR => (io::ErrorKind::NotADirectory, "not a directory"),
+            Errno::EISDIR => (io::ErrorKind::IsADirectory, "is a directory"),
+            Errno::EINVAL => (io::ErrorKind::InvalidInput, "invalid argument"),
+            Errno::ENOSPC => (io::ErrorKind::StorageFull, "no space left on device"),
+            Errno::EROFS => (io::ErrorKind::ReadOnlyFilesystem, "read-only file system"),
+            Errno::ENOTEMPTY => (io::ErrorKind::DirectoryNotEmpty, "directory not empty"),
+            Errno::EBADR => (io::ErrorKind::InvalidInput, "invalid request descriptor"),
+            Errno::EBADRQC => (io::ErrorKind::Unsupported, "invalid request code"),
+            Errno::EGENERIC => (io::ErrorKind::Other, "generic error"),
+        }
+    }
+
+    /// Convert a status code reported by the radio into an I/O error
+    pub fn error(status: u8) -> io::Error {
+        match Errno::try_from(status) {
+            Ok(errno) => io::Error::new(errno.describe().0, errno.to_string()),
+            Err(_) => io::Error::other(format!("unknown error code {status}")),
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self, self.describe().1)
+    }
+}
+
 impl TryFrom<u8> for Protocol {
     type Error = ();
 
@@ -80,6 +139,9 @@ impl TryFrom<u8> for Protocol {
     }
 }
 
+/// Function receiving the content of STDIO frames
+pub type StdioHandler = fn(&[u8]);
+
 #[derive(Debug)]
 pub struct Frame {
     pub proto: Protocol,
@@ -102,11 +164,37 @@ impl Frame {
     }
 }
 
+/// Traffic counters of a link
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LinkStats {
+    pub tx_frames:  u64, // Frames sent
+    pub rx_frames:  u64, // Frames received
+    pub tx_bytes:   u64, // Bytes sent, including framing
+    pub rx_bytes:   u64, // Bytes received, including framing
+    pub crc_errors: u64, // Frames discarded for bad CRC
+}
+
 pub struct Link {
     port: Option<Box <dyn SerialPort>>,
+    rx_buffer: VecDeque<u8>,      // Received bytes not yet decoded
+    rx_frames: VecDeque<Vec<u8>>, // Decoded frames not yet returned
+    stats: LinkStats,
 }
 
 impl Link {
+    const fn closed() -> Link {
+        Link {
+            port: None,
+            rx_buffer: VecDeque::new(),
+            rx_frames: VecDeque::new(),
+            stats: LinkStats { tx_frames: 0, rx_frames: 0, tx_bytes: 0, rx_bytes: 0, crc_errors: 0 },
+        }
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
     // This operation has to be performed only once, subsequent calls need to be get
     pub fn new(port: &str) -> io::Result<()> {
         // This is the serial port used for the rtxlink connection
@@ -115,14 +203,14 @@ impl Link {
             let serial_port = serialport::new(port, 115_200)
                                          .timeout(Duration::from_millis(2000))
                                          .open()?;
-            LINK = Link{port: Some(serial_port)};
+            LINK = Link{port: Some(serial_port), ..Link::closed()};
             Ok(())
         }
     }
 
     pub fn acquire() -> Link {
         unsafe {
-            replace(&mut LINK, Link { port: None })
+            replace(&mut LINK, Link::closed())
         }
     }
 
@@ -132,6 +220,32 @@ impl Link {
         }
     }
 
+    /// Register a function to be called with the content of every STDIO
+    /// frame received, or remove it
+    pub fn set_stdio_handler(handler: Option<StdioHandler>) {
+        *STDIO_HANDLER.lock().unwrap() = handler;
+    }
+
+    /// Read the data received so far without waiting and check whether it
+    /// completes a frame, in which case receive() returns without blocking
+    pub fn frame_ready(&mut self) -> io::Result<bool> {
+        if let Some(port) = self.port.as_mut() {
+            let available = port.bytes_to_read()? as usize;
+            if self.rx_frames.is_empty() && available > 0 {
+                let mut receive_buffer: Vec<u8> = vec![0; available];
+                let nread = port.read(&mut receive_buffer)?;
+                self.rx_buffer.extend(&receive_buffer[..nread]);
+                self.stats.rx_bytes += nread as u64;
+                match slip::decode_frames(&mut self.rx_buffer) {
+                    Ok(frames) => self.rx_frames.extend(frames),
+                    // Drop the undecodable data, it would fail again
+                    Err(e) => { self.rx_buffer.clear(); return Err(e) },
+                }
+            }
+        }
+        Ok(!self.rx_frames.is_empty())
+    }
+
     /// This function sends out a frame over a serial line, wrapped in slip
     /// and with the appropriate frame encoding.
     /// This function takes ownership of the Frame
@@ -142,41 +256,55 @@ impl Link {
         // Send frame down the serial port
         // println!("Tx: {:x?}", encoded);
         self.port.as_mut().unwrap().write_all(encoded.as_slice()).expect("Error in sending frame");
+        self.stats.tx_frames += 1;
+        self.stats.tx_bytes += encoded.len() as u64;
     }
 
     /// This function listens on the serial line for a frame, unwraps it,
     /// checks the CRC and returns it to the caller for dispatching.
     pub fn receive(&mut self) -> Result<Frame, io::ErrorKind> {
         // Enqueue data until we get the first valid packet
-        let mut decode_buffer = VecDeque::<u8>::new();
-        let frames: Vec<Vec<u8>> = loop {
+        while self.rx_frames.is_empty() {
             let mut receive_buffer: Vec<u8> = vec![0; 1024];
-            let nread = self.port.as_mut().unwrap().read(&mut receive_buffer).expect("Error during serial rx");
-            for i in 0..nread {
-                decode_buffer.push_back(receive_buffer[i]);
-            }
-            // println!("Rx: {:x?} N={:?}", decode_buffer, nread);
+            let nread = self.port.as_mut().unwrap().read(&mut receive_buffer).map_err(|e| e.kind())?;
+            self.rx_buffer.extend(&receive_buffer[..nread]);
+            self.stats.rx_bytes += nread as u64;
+            // println!("Rx: {:x?} N={:?}", self.rx_buffer, nread);
 
-            // Decode SLIP framing
-            let frames = slip::decode_frames(&mut decode_buffer).expect("Error in SLIP decode");
+            // Decode SLIP framing, frames after the first are kept for the next calls
+            let frames = match slip::decode_frames(&mut self.rx_buffer) {
+                Ok(frames) => frames,
+                // Drop the undecodable data, it would fail again
+                Err(e) => { self.rx_buffer.clear(); return Err(e.kind()) },
+            };
             // println!("Rx Frames: {:x?}", frames);
-            if frames.len() > 0 {
-                break frames
-            }
-        };
+            self.rx_frames.extend(frames);
+        }
+        let raw_frame = self.rx_frames.pop_front().unwrap();
 
         // Check CRC16 using CCITT polynomial
-        let digest = State::<AUG_CCITT>::calculate(&frames[0]);
+        let digest = State::<AUG_CCITT>::calculate(&raw_frame);
         if digest != 0x0000 {
+            self.stats.crc_errors += 1;
             return Err(io::ErrorKind::InvalidData);
         }
-        // Assign correct protocol
-        let proto = Protocol::try_from(frames[0][0]).expect("Protocol not implemented!");
+        self.stats.rx_frames += 1;
+        // Assign correct protocol, a frame holds at least the protocol and CRC
+        if raw_frame.len() < 3 {
+            return Err(io::ErrorKind::InvalidData);
+        }
+        let proto = Protocol::try_from(raw_frame[0]).map_err(|_| io::ErrorKind::InvalidData)?;
         // Trim proto (1 byte at beginning) and CRC (1 byte at end)
-        let data = &frames[0][1..frames[0].len() - 2];
+        let data = &raw_frame[1..raw_frame.len() - 2];
         let frame = Frame {proto: proto, data: Vec::from(data)};
+        if let Protocol::STDIO = frame.proto {
+            if let Some(handler) = *STDIO_HANDLER.lock().unwrap() {
+                handler(&frame.data);
+            }
+        }
         Ok(frame)
     }
 }
 
-static mut LINK: Link = Link { port: None };
+static mut LINK: Link = Link::closed();
+static STDIO_HANDLER: Mutex<Option<StdioHandler>> = Mutex::new(None);
@@ -3,15 +3,13 @@ use std::process;
 use text_colorizer::*;
 use std::sync::mpsc::channel;
 
-mod cat;
-mod dat;
-mod flow;
-mod fmp;
-mod link;
-mod slip;
+use rtxlink::{bandplan, cat, flow, fmp, hex, link, monitor, script, shell, sync, term};
+use rtxlink::bandplan::{BandPlan, BANDPLAN_ENV};
+use rtxlink::checksum::Algorithm;
+use rtxlink::freq::{parse_offset, Frequency};
 
 /// Print usage information of this tool
-fn print_usage(cmd: &String) {
+fn print_usage(cmd: &String) -> ! {
     eprintln!("{}: OpenRTX Communication Protocol", "rtxlink".yellow());
     eprintln!("{}: invalid parameters", "Error".red().bold());
     eprintln!("Usage: {cmd} SERIALPORT COMMAND [DATA_0..DATA_N]");
@@ -19,84 +17,777 @@ fn print_usage(cmd: &String) {
     eprintln!(" info                      Get device info");
     eprintln!(" freqrx                    Print receive frequency");
     eprintln!(" freqtx                    Print transmit frequency");
-    eprintln!(" freqrx FREQ_MHZ           Set the receive frequency");
-    eprintln!(" freqtx FREQ_MHZ           Set the transmit frequency");
-    eprintln!(" backup                    Read the device flash and save it to flash_dump.bin");
-    eprintln!(" restore MEM_IDX FILE      Write an image to the device flash");
+    eprintln!(" freqrx FREQ               Set the receive frequency");
+    eprintln!(" freqtx FREQ               Set the transmit frequency");
+    eprintln!(" tune RX [--shift OFFSET | --tx FREQ]");
+    eprintln!("                           Set receive and transmit frequency together,");
+    eprintln!("                           OFFSET is a signed repeater shift (e.g. -600k)");
+    eprintln!(" time [get|sync]           Print the radio clock drift or set it to host UTC");
+    eprintln!(" gps [--nmea]              Print the GPS position, or stream it as NMEA");
+    eprintln!("                           sentences on a pseudo-terminal");
+    eprintln!(" run SCRIPT                Execute a file of commands over one connection");
+    eprintln!(" watch                     Print notifications sent by the radio");
+    eprintln!(" monitor [--interval MS]   Show a live telemetry dashboard");
+    eprintln!(" shell                     Start an interactive session");
+    eprintln!(" ls [PATH] [-l] [-R]       List a directory of the radio filesystem (default /),");
+    eprintln!("                           -l shows types and sizes, -R lists subdirectories");
+    eprintln!(" get REMOTE [LOCAL]        Download a file from the radio filesystem");
+    eprintln!(" put LOCAL [REMOTE]        Upload a file to the radio filesystem");
+    eprintln!(" mv FROM TO                Move or rename a file on the radio filesystem");
+    eprintln!(" cp FROM TO                Copy a file on the radio filesystem");
+    eprintln!(" mkdir [-p] PATH..         Create directories, -p also creates missing parents");
+    eprintln!(" rm [-r] PATH..            Remove files or empty directories, -r removes");
+    eprintln!("                           directories with their content");
+    eprintln!(" sync LOCAL REMOTE [--push|--pull] [--delete] [--dry-run] [--checksum]");
+    eprintln!("                           Synchronise a host directory with a radio directory,");
+    eprintln!("                           by default copying what is missing on either side");
+    eprintln!(" raw cat get|set ID [HEX]  Send a CAT request, ID is two characters (e.g. IN)");
+    eprintln!("                           or a number");
+    eprintln!(" raw fmp OPCODE [HEX..]    Send an FMP command with one parameter per argument");
+    eprintln!(" raw frame PROTO HEX       Send a frame (stdio, cat, fmp, dat or a number)");
+    eprintln!("                           and print the next frame received");
+    eprintln!(" reset, reboot             Reboot the radio, leaving file transfer mode");
+    eprintln!(" backup                    Save all the memories of the device in an archive,");
+    eprintln!("                           together with a manifest describing them");
+    eprintln!(" checksum [MEM_IDX] [FILE] [--sha256]");
+    eprintln!("                           Print the CRC32 or SHA-256 of the memories computed");
+    eprintln!("                           by the radio, or check whether a memory matches FILE");
+    eprintln!(" dump MEM_IDX [FILE]       Read a memory, or a region of it, into FILE");
+    eprintln!("                           (default dump_MEM_IDX.bin)");
+    eprintln!(" restore [MEM_IDX] FILE    Write an image to the device flash; with a region,");
+    eprintln!("                           FILE holds either the region or the whole memory.");
+    eprintln!("                           A backup archive restores the memories listed in");
+    eprintln!("                           its manifest, or only MEM_IDX");
+    eprintln!("options:");
+    eprintln!(" --bandplan PLAN           Band plan for freqtx: r1, r2, r3, iaru or a file");
+    eprintln!("                           (default: ${BANDPLAN_ENV} or iaru, the bands shared");
+    eprintln!("                           by all regions)");
+    eprintln!(" --force                   Transmit outside of the band plan, or restore a");
+    eprintln!("                           raw image or a backup of another radio or memory");
+    eprintln!(" --offset X, --length Y    Region of the memory for dump, restore and checksum,");
+    eprintln!("                           in Bytes or k/M, e.g. --offset 0x8000 --length 4k");
+    eprintln!(" --no-verify               Do not check the memory content after restore");
+    eprintln!(" --diff                    Restore only the blocks that differ from the image");
+    eprintln!(" --backup                  Save the memories to an archive before restore");
+    eprintln!(" --yes                     Restore without asking for confirmation");
+    eprintln!("frequencies default to MHz, units Hz/kHz/MHz/GHz or k/M/G are accepted:");
+    eprintln!(" 430.0125, 145500k, 145.500.000, 1.2975G");
     process::exit(1);
 }
 
+/// Remove a flag from the argument list, returning whether it was present
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => { args.remove(i); true },
+        None => false,
+    }
+}
+
+/// Remove an option and its value from the argument list, returning the value
+fn take_option(args: &mut Vec<String>, option: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == option)?;
+    args.remove(i);
+    if i < args.len() { Some(args.remove(i)) } else { None }
+}
+
+/// Load the band plan selected on the command line or in the environment
+fn load_bandplan(spec: Option<String>) -> BandPlan {
+    let spec = spec.or_else(|| env::var(BANDPLAN_ENV).ok())
+                   .unwrap_or(String::from("iaru"));
+    match BandPlan::load(&spec) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("{}: cannot load band plan {spec}: {e}", "Error".red().bold());
+            process::exit(1);
+        }
+    }
+}
+
 /// Print info about the target OpenRTX platform
 fn print_info() {
-    println!("Radio identifier: {}", cat::info());
-    let mem_list = fmp::meminfo();
+    let info = cat::info().unwrap_or_else(|e| {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    });
+    println!("Radio model: {}", info.model);
+    let fields = [("Hardware revision", &info.hw_rev), ("Firmware version", &info.firmware),
+                  ("Git hash", &info.git_hash), ("Serial number", &info.serial)];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            println!("{name}: {value}");
+        }
+    }
+    let mem_list = match fmp::meminfo() {
+        Ok(mem_list) => mem_list,
+        Err(e) => { eprintln!("{}: {e}", "Error".red().bold()); process::exit(1) },
+    };
     println!("Available memories:");
-    let mut i: usize = 0;
-    for mem in mem_list {
-        println!("[{}]: {:?}", i, mem);
-        i += 1;
+    for (i, mem) in mem_list.iter().enumerate() {
+        println!("[{}]: {:?} {}", i, mem, mem.flags);
+    };
+}
+
+fn cli_tune(rx: Option<String>, shift: Option<String>, tx: Option<String>, plan: Option<&BandPlan>) {
+    let parse = |f: &str| f.parse::<Frequency>().unwrap_or_else(|e| {
+        eprintln!("{}: invalid frequency \"{f}\": {e}", "Error".red().bold());
+        process::exit(1);
+    });
+    let rx = match rx {
+        Some(rx) => parse(&rx),
+        None => { eprintln!("{}: missing receive frequency", "Error".red().bold()); process::exit(1) },
+    };
+    let tx = match (shift, tx) {
+        (None, None) => rx,
+        (None, Some(tx)) => parse(&tx),
+        (Some(shift), None) => {
+            let offset = parse_offset(&shift).unwrap_or_else(|e| {
+                eprintln!("{}: invalid shift \"{shift}\": {e}", "Error".red().bold());
+                process::exit(1);
+            });
+            rx.offset(offset).unwrap_or_else(|| {
+                eprintln!("{}: shift {shift} from {rx} is out of range", "Error".red().bold());
+                process::exit(1);
+            })
+        },
+        (Some(_), Some(_)) => {
+            eprintln!("{}: --shift and --tx are mutually exclusive", "Error".red().bold());
+            process::exit(1);
+        },
+    };
+    if let Err(e) = bandplan::check_tx(tx, plan) {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    }
+    match flow::tune(rx, tx) {
+        Ok(_) => println!("Rx: {rx}\nTx: {tx}"),
+        Err(e) => { eprintln!("{}: {e}", "Error".red().bold()); process::exit(1) },
+    }
+}
+
+/// Print radio clock, host clock and their difference
+fn print_time() -> std::io::Result<()> {
+    let radio = cat::get_time()?;
+    let host = chrono::Utc::now();
+    println!("Radio time: {}", radio.format("%Y-%m-%d %H:%M:%S UTC"));
+    println!("Host time:  {}", host.format("%Y-%m-%d %H:%M:%S UTC"));
+    println!("Drift: {}", cat::drift(radio, host));
+    Ok(())
+}
+
+fn cli_time(action: Option<String>) {
+    let result = match action.as_deref() {
+        None | Some("get") => print_time(),
+        Some("sync") => cat::set_time(chrono::Utc::now()).and_then(|_| print_time()),
+        Some(action) => {
+            eprintln!("{}: unknown time action \"{action}\"", "Error".red().bold());
+            process::exit(1);
+        },
+    };
+    if let Err(e) = result {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    }
+}
+
+/// Poll the radio GPS and write NMEA sentences to a new pseudo-terminal
+#[cfg(unix)]
+fn gps_nmea() -> std::io::Result<()> {
+    use serialport::SerialPort;
+    use std::io::{ErrorKind, Write};
+    let (mut master, slave) = serialport::TTYPort::pair()?;
+    println!("NMEA sentences available on {}", slave.name().unwrap_or_default());
+    let mut cut = false;
+    loop {
+        let fix = cat::gps()?;
+        // A sentence cut short by the previous batch is terminated first, so
+        // that readers discard it instead of joining it with the next one
+        let mut batch = String::from(if cut { "\r\n" } else { "" });
+        batch.extend(fix.nmea(chrono::Utc::now()));
+        // Drop the rest of the batch while nobody is reading the other end
+        cut = match master.write_all(batch.as_bytes()) {
+            Err(e) if e.kind() == ErrorKind::TimedOut => true,
+            result => { result?; false },
+        };
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+#[cfg(not(unix))]
+fn gps_nmea() -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported,
+                            "NMEA output requires pseudo-terminal support"))
+}
+
+fn cli_gps(nmea: bool) {
+    let result = match nmea {
+        true => gps_nmea(),
+        false => cat::gps().map(|fix| println!("{fix}")),
     };
+    if let Err(e) = result {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    }
+}
+
+fn cli_run(port: &str, file: Option<String>, plan: Option<&BandPlan>) {
+    let file = file.unwrap_or_else(|| {
+        eprintln!("{}: missing script file", "Error".red().bold());
+        process::exit(1);
+    });
+    // Parse the whole script before talking to the radio
+    let statements = std::fs::read_to_string(&file).and_then(|text| script::parse(&file, &text));
+    let statements = statements.unwrap_or_else(|e| {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    });
+    link::Link::new(port).expect("Error in opening serial port!");
+    if let Err(e) = script::run(&file, &statements, plan) {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    }
+}
+
+fn cli_watch() -> std::io::Result<()> {
+    cat::on_event(std::sync::Arc::new(|event| {
+        println!("[{}] {event}", chrono::Local::now().format("%H:%M:%S%.3f"));
+    }));
+    cat::subscribe(&cat::EventKind::ALL)?;
+    // In raw mode Ctrl-C arrives as a key press, so that notifications are
+    // disabled before leaving
+    let raw = match term::is_interactive() {
+        true => Some(term::RawMode::enable()?),
+        false => None,
+    };
+    println!("Watching radio notifications, press q or Ctrl-C to stop");
+    let result = loop {
+        let wait = match cat::poll_events() {
+            Ok(0) => std::time::Duration::from_millis(20),
+            Ok(_) => std::time::Duration::ZERO,
+            // Skip frames corrupted or cut short instead of giving up
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::InvalidData | std::io::ErrorKind::TimedOut) => {
+                eprintln!("Skipped bad frame: {e}");
+                std::time::Duration::ZERO
+            },
+            Err(e) => break Err(e),
+        };
+        let key = match raw {
+            Some(_) => term::read_key(wait),
+            None => { std::thread::sleep(wait); Ok(None) },
+        };
+        match key {
+            Ok(Some(b'q')) | Ok(Some(0x03)) => break Ok(()),
+            Ok(_) => (),
+            Err(e) => break Err(e),
+        }
+    };
+    drop(raw);
+    let unsubscribe = cat::subscribe(&[]);
+    result.and(unsubscribe)
+}
+
+/// Print the content of a radio directory, and of its subdirectories if recursive
+fn cli_ls(path: &str, long: bool, recursive: bool) -> std::io::Result<()> {
+    let mut entries = fmp::list(path)?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    if recursive {
+        println!("{path}:");
+    }
+    for entry in &entries {
+        let name = if entry.is_dir() { format!("{}/", entry.name).blue().bold().to_string() } else { entry.name.clone() };
+        match long {
+            true => println!("{} {:>10}  {name}", if entry.is_dir() { 'd' } else { '-' }, entry.size),
+            false => println!("{name}"),
+        }
+    }
+    if recursive {
+        for entry in entries.iter().filter(|e| e.is_dir()) {
+            println!();
+            cli_ls(&fmp::join_path(path, &entry.name), long, recursive)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run a file management command on the radio filesystem
+fn cli_fs(command: &str, params: &[String], parents: bool, recursive: bool) -> std::io::Result<()> {
+    let params: Vec<&str> = params.iter().map(|p| p.as_str()).collect();
+    // Report the failing operation and path along with the radio error
+    let context = |path: &str, e: std::io::Error| std::io::Error::new(e.kind(), format!("{command} {path}: {e}"));
+    match (command, params.as_slice()) {
+        ("mv", [from, to]) => fmp::rename(from, to).map_err(|e| context(from, e)),
+        ("cp", [from, to]) => fmp::copy(from, to).map_err(|e| context(from, e)),
+        ("mkdir", paths) if !paths.is_empty() => {
+            for path in paths {
+                let result = if parents { fmp::mkdir_all(path) } else { fmp::mkdir(path) };
+                result.map_err(|e| context(path, e))?;
+            }
+            Ok(())
+        },
+        ("rm", paths) if !paths.is_empty() => {
+            for path in paths {
+                let result = if recursive { fmp::remove_all(path) } else { fmp::remove(path) };
+                result.map_err(|e| context(path, e))?;
+            }
+            Ok(())
+        },
+        _ => Err(std::io::Error::other("usage: mv FROM TO, cp FROM TO, mkdir [-p] PATH.. or rm [-r] PATH..")),
+    }
+}
+
+/// Parse a number given in decimal or, with a 0x prefix, in hexadecimal
+fn parse_number(text: &str) -> Result<u64, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number \"{text}\""))
+}
+
+/// Parse a memory offset or length, in Bytes unless followed by k or M (KiB, MiB)
+fn parse_size(text: Option<String>, name: &str) -> Option<u32> {
+    let text = text?;
+    let (number, scale) = match text.strip_suffix(['k', 'K']) {
+        Some(n) => (n, 1024),
+        None => match text.strip_suffix('M') {
+            Some(n) => (n, 1024 * 1024),
+            None => (text.as_str(), 1),
+        },
+    };
+    let size = parse_number(number).ok()
+                                   .and_then(|n| n.checked_mul(scale))
+                                   .and_then(|n| u32::try_from(n).ok());
+    match size {
+        Some(size) => Some(size),
+        None => { eprintln!("{}: invalid {name} \"{text}\"", "Error".red().bold()); process::exit(1) },
+    }
+}
+
+/// Parse a CAT ID, either as its two characters (e.g. IN) or as a number
+fn parse_cat_id(text: &str) -> Result<u16, String> {
+    match text.as_bytes() {
+        [hi, lo] if text.is_ascii() && !text.starts_with(|c: char| c.is_ascii_digit()) => {
+            Ok(u16::from_be_bytes([*hi, *lo]))
+        },
+        _ => u16::try_from(parse_number(text)?).map_err(|_| format!("CAT ID \"{text}\" out of range")),
+    }
+}
+
+fn parse_protocol(text: &str) -> Result<link::Protocol, String> {
+    match text.to_ascii_lowercase().as_str() {
+        "stdio" => Ok(link::Protocol::STDIO),
+        "cat" => Ok(link::Protocol::CAT),
+        "fmp" => Ok(link::Protocol::FMP),
+        "dat" => Ok(link::Protocol::DAT),
+        _ => u8::try_from(parse_number(text)?).ok()
+                                              .and_then(|p| link::Protocol::try_from(p).ok())
+                                              .ok_or(format!("unknown protocol \"{text}\"")),
+    }
+}
+
+/// Send a request built from raw values and hex-dump the reply
+fn cli_raw(params: &[String]) -> std::io::Result<()> {
+    let params: Vec<&str> = params.iter().map(|p| p.as_str()).collect();
+    let invalid = std::io::Error::other;
+    match params.as_slice() {
+        ["cat", "get" | "G" | "g", id] => {
+            let reply = cat::get_raw(parse_cat_id(id).map_err(invalid)?)?;
+            print!("{}", hex::dump(&reply));
+        },
+        ["cat", "set" | "S" | "s", id, data @ ..] => {
+            let data = hex::parse(&data.concat()).map_err(invalid)?;
+            cat::set_raw(parse_cat_id(id).map_err(invalid)?, &data)?;
+            println!("OK");
+        },
+        ["fmp", opcode, data @ ..] => {
+            let opcode = u8::try_from(parse_number(opcode).map_err(invalid)?)
+                             .map_err(|_| invalid(format!("opcode \"{opcode}\" out of range")))?;
+            let params = data.iter()
+                             .map(|d| hex::parse(d))
+                             .collect::<Result<Vec<Vec<u8>>, String>>()
+                             .map_err(invalid)?;
+            fmp::send_raw(opcode, &params)?;
+            for (i, param) in fmp::wait_reply_raw(opcode)?.iter().enumerate() {
+                println!("param {i} ({} bytes):", param.len());
+                print!("{}", hex::dump(param));
+            }
+        },
+        ["frame", proto, data @ ..] => {
+            let proto = parse_protocol(proto).map_err(invalid)?;
+            let data = hex::parse(&data.concat()).map_err(invalid)?;
+            let mut link = link::Link::acquire();
+            link.send(link::Frame { proto, data });
+            let frame = link.receive();
+            link.release();
+            let frame = frame.map_err(std::io::Error::from)?;
+            println!("{:?} frame ({} bytes):", frame.proto, frame.data.len());
+            print!("{}", hex::dump(&frame.data));
+        },
+        _ => return Err(std::io::Error::other("usage: raw cat get|set ID [HEX], raw fmp OPCODE [HEX..] or raw frame PROTO HEX")),
+    }
+    Ok(())
+}
+
+fn cli_get(port: String, remote: Option<String>, local: Option<String>) {
+    let remote = remote.unwrap_or_else(|| {
+        eprintln!("{}: missing remote file", "Error".red().bold());
+        process::exit(1);
+    });
+    // Save to the current directory with the remote name unless told otherwise
+    let name = remote.rsplit('/').next().unwrap_or(&remote).to_string();
+    let local = match (local.map(std::path::PathBuf::from), name.is_empty()) {
+        (Some(path), _) if !path.is_dir() => path,
+        // A directory has no name to save it with
+        (_, true) => {
+            eprintln!("{}: {remote} is a directory, only files can be downloaded", "Error".red().bold());
+            process::exit(1);
+        },
+        (Some(dir), false) => dir.join(&name),
+        (None, false) => std::path::PathBuf::from(&name),
+    };
+    let local = local.to_string_lossy().into_owned();
+    let (progress_tx, progress_rx) = channel();
+    // Start download thread
+    let download = std::thread::spawn(move || {
+        link::Link::new(&port).expect("Error in opening serial port!");
+        fmp::read_file(&remote, &local, Some(&progress_tx))
+    });
+    // Progress printing loop, ends when the download thread terminates
+    for (receive_size, size) in progress_rx {
+        println!("Received: {receive_size:?}/{size:?}");
+    }
+    if let Err(e) = download.join().expect("Download thread panicked") {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    }
+}
+
+fn cli_put(port: String, local: Option<String>, remote: Option<String>) {
+    let local = local.unwrap_or_else(|| {
+        eprintln!("{}: missing local file", "Error".red().bold());
+        process::exit(1);
+    });
+    // Upload to the root directory with the local name unless told otherwise
+    let name = std::path::Path::new(&local).file_name()
+                                           .map(|n| n.to_string_lossy().into_owned())
+                                           .unwrap_or(local.clone());
+    let remote = match remote {
+        Some(dir) if dir.ends_with('/') => fmp::join_path(&dir, &name),
+        Some(path) => path,
+        None => fmp::join_path("/", &name),
+    };
+    let (progress_tx, progress_rx) = channel();
+    // Start upload thread
+    let upload = std::thread::spawn(move || {
+        link::Link::new(&port).expect("Error in opening serial port!");
+        fmp::write_file(&local, &remote, Some(&progress_tx))
+    });
+    // Progress printing loop, ends when the upload thread terminates
+    for (send_size, size) in progress_rx {
+        println!("Sent: {send_size:?}/{size:?}");
+    }
+    if let Err(e) = upload.join().expect("Upload thread panicked") {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    }
 }
 
 fn cli_backup(port: String) {
     let (progress_tx, progress_rx) = channel();
     // Start backup thread
-    std::thread::spawn(move || {
-        rtxlink::link::Link::new(&port).expect("Error in opening serial port!");
-        rtxlink::flow::backup(None, Some(&progress_tx));
+    let backup = std::thread::spawn(move || {
+        link::Link::new(&port).expect("Error in opening serial port!");
+        rtxlink::flow::backup(None, Some(&progress_tx))
     });
-    // Progress printing loop
-    let mut receive_size = 0;
-    let mut size = 1;
-    while receive_size < size {
-        match progress_rx.recv() {
-            Ok(x) => {
-                (receive_size, size) = x;
-                println!("Received: {receive_size:?}/{size:?}");
-            }
-            Err(_) => (),
-        }
+    // Progress printing loop, ends when the backup thread terminates
+    for (receive_size, size) in progress_rx {
+        println!("Received: {receive_size:?}/{size:?}");
+    }
+    match backup.join().expect("Backup thread panicked") {
+        Ok(archive) => {
+            println!("Saved {} memories to {}", archive.memories, archive.path);
+            if archive.unchanged > 0 {
+                println!("{} unchanged since an earlier backup", archive.unchanged);
+            }
+        },
+        Err(e) => {
+            eprintln!("{}: {e}", "Error".red().bold());
+            process::exit(1);
+        },
+    }
+}
+
+/// Print the checksums computed by the radio, or compare a memory with an image
+fn cli_checksum(mem_idx: Option<String>, file: Option<String>, offset: Option<u32>, length: Option<u32>,
+                algorithm: Algorithm) -> std::io::Result<()> {
+    let mem_list = fmp::meminfo()?;
+    let mem_idx = match mem_idx {
+        Some(m) => Some(m.parse::<usize>().ok()
+                         .filter(|m| *m < mem_list.len())
+                         .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid memory index"))?),
+        None => None,
+    };
+    match (mem_idx, file) {
+        (Some(i), Some(file)) => {
+            let image = std::fs::read(&file)?;
+            match flow::matches(i, &image, offset, length)? {
+                true => println!("[{i}]: {:?} matches {file}", mem_list[i]),
+                false => {
+                    println!("[{i}]: {:?} differs from {file}", mem_list[i]);
+                    process::exit(1);
+                },
+            }
+        },
+        (mem_idx, _) => {
+            for (i, mem) in mem_list.iter().enumerate().filter(|(i, _)| mem_idx.is_none_or(|m| m == *i)) {
+                let range = fmp::MemRange::new(mem, offset, length)?;
+                let digest = fmp::checksum(i, mem, Some(range), algorithm)?;
+                println!("[{i}]: {:?} {range} {algorithm:?} {}", mem, hex::encode(&digest));
+            }
+        },
     }
+    Ok(())
 }
 
-fn cli_restore(port: String, mem_idx: Option<String>, file: Option<String>) {
+fn cli_dump(port: String, mem_idx: Option<String>, file: Option<String>, offset: Option<u32>, length: Option<u32>) {
+    let mem_idx = mem_idx.and_then(|m| m.parse::<usize>().ok()).unwrap_or_else(|| {
+        eprintln!("{}: missing or invalid memory index", "Error".red().bold());
+        process::exit(1);
+    });
+    let file = file.unwrap_or(format!("dump_{mem_idx}.bin"));
     let (progress_tx, progress_rx) = channel();
-    // Start backup thread
-    std::thread::spawn(move || {
-        rtxlink::link::Link::new(&port).expect("Error in opening serial port!");
-        rtxlink::flow::restore(mem_idx, file, Some(&progress_tx));
+    // Start dump thread
+    let dump = std::thread::spawn(move || {
+        link::Link::new(&port).expect("Error in opening serial port!");
+        rtxlink::flow::dump(mem_idx, &file, offset, length, Some(&progress_tx)).map(|_| file)
     });
-    // Progress printing loop
-    let mut send_size = 0;
-    let mut size = 1;
-    while send_size < size {
-        match progress_rx.recv() {
-            Ok(x) => {
-                (send_size, size) = x;
-                println!("Sent: {send_size:?}/{size:?}");
-            }
-            Err(_) => (),
-        }
+    // Progress printing loop, ends when the dump thread terminates
+    for (receive_size, size) in progress_rx {
+        println!("Received: {receive_size:?}/{size:?}");
+    }
+    match dump.join().expect("Dump thread panicked") {
+        Ok(file) => println!("Saved to {file}"),
+        Err(e) => {
+            eprintln!("{}: {e}", "Error".red().bold());
+            process::exit(1);
+        },
+    }
+}
+
+/// Ask the user to confirm on the terminal, anything but yes declines
+fn confirm(question: &str) -> bool {
+    use std::io::Write;
+    print!("{question} [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+fn cli_restore(port: String, mem_idx: Option<String>, file: Option<String>, options: flow::RestoreOptions, yes: bool) {
+    link::Link::new(&port).expect("Error in opening serial port!");
+    let plan = flow::plan_restore(mem_idx, file, &options).unwrap_or_else(|e| {
+        eprintln!("{}: {e}", "Error".red().bold());
+        process::exit(1);
+    });
+    println!("{plan}");
+    if !yes && !confirm("Overwrite these memories?") {
+        eprintln!("Restore cancelled");
+        process::exit(1);
+    }
+    let (progress_tx, progress_rx) = channel();
+    // Start restore thread
+    let restore = std::thread::spawn(move || {
+        rtxlink::flow::execute_restore(&plan, &options, Some(&progress_tx))
+    });
+    // Progress printing loop, ends when the restore thread terminates
+    for (send_size, size) in progress_rx {
+        println!("Sent: {send_size:?}/{size:?}");
+    }
+    match restore.join().expect("Restore thread panicked") {
+        Ok(written) => {
+            println!("Wrote {written} Bytes");
+            if options.verify {
+                println!("Verified");
+            }
+        },
+        Err(e) => {
+            eprintln!("{}: {e}", "Error".red().bold());
+            process::exit(1);
+        },
     }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    // Options of a command are only taken for it, so that the other commands
+    // can receive the same words as paths
+    let command = args.get(2).cloned().unwrap_or_default();
+    let transmits = matches!(command.as_str(), "freqtx" | "tune" | "run" | "shell");
+    let force = (transmits || command == "restore") && take_flag(&mut args, "--force");
+    let bandplan = match transmits {
+        true => take_option(&mut args, "--bandplan"),
+        false => None,
+    };
+    let interval = match command.as_str() {
+        "monitor" => take_option(&mut args, "--interval"),
+        _ => None,
+    };
+    let is_sync = command == "sync";
+    let push = is_sync && take_flag(&mut args, "--push");
+    let pull = is_sync && take_flag(&mut args, "--pull");
+    let delete = is_sync && take_flag(&mut args, "--delete");
+    let dry_run = is_sync && take_flag(&mut args, "--dry-run");
+    let checksum = is_sync && take_flag(&mut args, "--checksum");
+    let no_verify = command == "restore" && take_flag(&mut args, "--no-verify");
+    let sha256 = command == "checksum" && take_flag(&mut args, "--sha256");
+    let diff = command == "restore" && take_flag(&mut args, "--diff");
+    let backup = command == "restore" && take_flag(&mut args, "--backup");
+    let yes = command == "restore" && take_flag(&mut args, "--yes");
+    let (offset, length) = match command.as_str() {
+        "dump" | "restore" | "checksum" => (parse_size(take_option(&mut args, "--offset"), "offset"),
+                                            parse_size(take_option(&mut args, "--length"), "length")),
+        _ => (None, None),
+    };
+    let (shift, tx) = match command.as_str() {
+        "tune" => (take_option(&mut args, "--shift"), take_option(&mut args, "--tx")),
+        _ => (None, None),
+    };
+    let (long, recursive) = match command.as_str() {
+        "ls" => (take_flag(&mut args, "-l"), take_flag(&mut args, "-R")),
+        _ => (false, false),
+    };
+    let nmea = command == "gps" && take_flag(&mut args, "--nmea");
+    let parents = command == "mkdir" && take_flag(&mut args, "-p");
+    let remove_all = command == "rm" && take_flag(&mut args, "-r");
 
     // Print usage information
     if args.len() < 3 { print_usage(&args[0]); }
 
     let serial_port = &args[1];
     let command = &args[2];
-    let data_0 = env::args().nth(3);
-    let data_1 = env::args().nth(4);
+    let data_0 = args.get(3).cloned();
+    let data_1 = args.get(4).cloned();
 
     match &command as &str {
         "info" => { link::Link::new(serial_port).expect("Error in opening serial port!"); print_info() },
-        "freqrx" => { link::Link::new(serial_port).expect("Error in opening serial port!"); cat::freq(data_0, false) },
-        "freqtx" => { link::Link::new(serial_port).expect("Error in opening serial port!"); cat::freq(data_0, true) },
+        "freqrx" => {
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = cat::freq(data_0, false, None) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "freqtx" => {
+            let plan = if force { None } else { Some(load_bandplan(bandplan)) };
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = cat::freq(data_0, true, plan.as_ref()) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "tune" => {
+            let plan = if force { None } else { Some(load_bandplan(bandplan)) };
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            cli_tune(data_0, shift, tx, plan.as_ref())
+        },
+        "time" => { link::Link::new(serial_port).expect("Error in opening serial port!"); cli_time(data_0) },
+        "gps" => { link::Link::new(serial_port).expect("Error in opening serial port!"); cli_gps(nmea) },
+        "run" => {
+            let plan = if force { None } else { Some(load_bandplan(bandplan)) };
+            cli_run(serial_port, data_0, plan.as_ref())
+        },
+        "watch" => {
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = cli_watch() {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "monitor" => {
+            let interval = match interval.map(|i| i.parse::<u64>()) {
+                None => 500,
+                Some(Ok(ms)) => ms,
+                Some(Err(_)) => { eprintln!("{}: invalid interval", "Error".red().bold()); process::exit(1) },
+            };
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = monitor::run(std::time::Duration::from_millis(interval)) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "shell" => {
+            let plan = if force { None } else { Some(load_bandplan(bandplan)) };
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = shell::run(plan.as_ref()) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "ls" => {
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = cli_ls(data_0.as_deref().unwrap_or("/"), long, recursive) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "mv" | "cp" | "mkdir" | "rm" => {
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = cli_fs(command, &args[3..], parents, remove_all) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "sync" => {
+            let direction = match (push, pull) {
+                (true, true) => { eprintln!("{}: --push and --pull are exclusive", "Error".red().bold()); process::exit(1) },
+                (true, false) => sync::Direction::PUSH,
+                (false, true) => sync::Direction::PULL,
+                (false, false) => sync::Direction::BOTH,
+            };
+            let (Some(local), Some(remote)) = (data_0, data_1) else { print_usage(&args[0]) };
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = sync::run(std::path::Path::new(&local), &remote, direction, delete, dry_run, checksum) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "raw" => {
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = cli_raw(&args[3..]) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "reset" | "reboot" => {
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = fmp::reset() {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "get" => cli_get(serial_port.clone(), data_0, data_1),
+        "put" => cli_put(serial_port.clone(), data_0, data_1),
         "backup" => cli_backup(serial_port.clone()),
-        "restore" => cli_restore(serial_port.clone(), data_0, data_1),
+        "checksum" => {
+            let algorithm = if sha256 { Algorithm::SHA256 } else { Algorithm::CRC32 };
+            link::Link::new(serial_port).expect("Error in opening serial port!");
+            if let Err(e) = cli_checksum(data_0, data_1, offset, length, algorithm) {
+                eprintln!("{}: {e}", "Error".red().bold());
+                process::exit(1);
+            }
+        },
+        "dump" => cli_dump(serial_port.clone(), data_0, data_1, offset, length),
+        "restore" => {
+            let options = flow::RestoreOptions { offset, length, verify: !no_verify, diff, force, backup };
+            // A single argument is a backup archive
+            let (mem_idx, file) = match data_1 {
+                Some(file) => (data_0, Some(file)),
+                None => (None, data_0),
+            };
+            cli_restore(serial_port.clone(), mem_idx, file, options, yes)
+        },
         _ => print_usage(&args[0]),
     };
 }
@@ -0,0 +1,200 @@
+//! This module handles the live telemetry dashboard
+
+/*!
+The dashboard polls the radio over CAT at a fixed interval and redraws the
+whole terminal: telemetry with history sparklines on the left, the STDIO
+stream and the link statistics on the right. Values the radio does not
+report are shown as `n/a`. Press `q` or Ctrl-C to quit.
+*/
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use text_colorizer::*;
+
+use crate::cat;
+use crate::cat::OpMode;
+use crate::freq::Frequency;
+use crate::link::{Link, LinkStats};
+use crate::term;
+use crate::term::{AlternateScreen, RawMode};
+
+/// Sparkline levels, from lowest to highest
+const SPARK: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Number of samples kept for each sparkline
+const HISTORY_SIZE: usize = 256;
+
+/// Maximum amount of STDIO text kept for display, in bytes
+const STDIO_SIZE: usize = 16384;
+
+static STDIO_TEXT: Mutex<String> = Mutex::new(String::new());
+
+/// Keep text received over STDIO for the side pane
+fn capture_stdio(data: &[u8]) {
+    let mut text = STDIO_TEXT.lock().unwrap();
+    text.push_str(&String::from_utf8_lossy(data));
+    if text.len() > STDIO_SIZE {
+        let mut cut = text.len() - STDIO_SIZE;
+        while !text.is_char_boundary(cut) {
+            cut += 1;
+        }
+        text.drain(..cut);
+    }
+}
+
+/// Values read from the radio in one polling round
+struct Telemetry {
+    rx: Option<Frequency>,
+    tx: Option<Frequency>,
+    mode: Option<OpMode>,
+    rssi: Option<i16>,        // dBm
+    battery: Option<u16>,     // mV
+    temperature: Option<f32>, // °C
+}
+
+impl Telemetry {
+    fn poll() -> Telemetry {
+        Telemetry {
+            rx: cat::get_freq(false).ok(),
+            tx: cat::get_freq(true).ok(),
+            mode: cat::opmode().ok(),
+            rssi: cat::rssi().ok(),
+            battery: cat::battery().ok(),
+            temperature: cat::temperature().ok(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct History {
+    rssi: VecDeque<f32>,
+    battery: VecDeque<f32>,
+    temperature: VecDeque<f32>,
+}
+
+impl History {
+    fn push(&mut self, t: &Telemetry) {
+        let samples = [(&mut self.rssi, t.rssi.map(|v| v as f32)),
+                       (&mut self.battery, t.battery.map(|v| v as f32)),
+                       (&mut self.temperature, t.temperature)];
+        for (history, sample) in samples {
+            if let Some(sample) = sample {
+                history.push_back(sample);
+                if history.len() > HISTORY_SIZE {
+                    history.pop_front();
+                }
+            }
+        }
+    }
+}
+
+/// Render the most recent samples of a history as a sparkline
+pub(crate) fn sparkline(history: &VecDeque<f32>, width: usize) -> String {
+    let samples: Vec<f32> = history.iter().rev().take(width).rev().copied().collect();
+    let min = samples.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = samples.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    samples.iter()
+           .map(|&v| match max > min {
+               true => SPARK[((v - min) / (max - min) * (SPARK.len() - 1) as f32).round() as usize],
+               false => SPARK[0],
+           })
+           .collect()
+}
+
+/// Truncate or pad a line to exactly the given width
+pub(crate) fn fit(text: &str, width: usize) -> String {
+    let mut line: String = text.chars().take(width).collect();
+    let len = line.chars().count();
+    line.extend(std::iter::repeat_n(' ', width - len));
+    line
+}
+
+fn or_na<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or(String::from("n/a"))
+}
+
+fn render(t: &Telemetry, history: &History, stats: &LinkStats, uptime: Duration) -> String {
+    let (cols, rows) = term::size();
+    let left_width = (cols / 2).clamp(cols.min(30), 60);
+    let right_width = cols.saturating_sub(left_width + 3);
+    let spark_width = left_width.saturating_sub(2);
+
+    let left = vec![
+        String::from("Radio"),
+        format!("Rx frequency  {}", or_na(t.rx)),
+        format!("Tx frequency  {}", or_na(t.tx)),
+        format!("Mode          {}", or_na(t.mode)),
+        String::new(),
+        format!("RSSI          {}", or_na(t.rssi.map(|r| format!("{r} dBm")))),
+        format!("  {}", sparkline(&history.rssi, spark_width)),
+        format!("Battery       {}", or_na(t.battery.map(|b| format!("{}.{:03} V", b / 1000, b % 1000)))),
+        format!("  {}", sparkline(&history.battery, spark_width)),
+        format!("Temperature   {}", or_na(t.temperature.map(|t| format!("{t:.1} °C")))),
+        format!("  {}", sparkline(&history.temperature, spark_width)),
+    ];
+
+    let link = vec![
+        String::from("Link"),
+        format!("Frames sent      {}", stats.tx_frames),
+        format!("Frames received  {}", stats.rx_frames),
+        format!("Bytes sent       {}", stats.tx_bytes),
+        format!("Bytes received   {}", stats.rx_bytes),
+        format!("CRC errors       {}", stats.crc_errors),
+        format!("Uptime           {} s", uptime.as_secs()),
+    ];
+    // STDIO takes the space left above the link statistics
+    let body_rows = rows.saturating_sub(2);
+    let stdio_rows = body_rows.saturating_sub(link.len() + 2);
+    let stdio = STDIO_TEXT.lock().unwrap().clone();
+    let stdio_lines: Vec<&str> = stdio.lines().collect();
+    let mut right = vec![String::from("STDIO")];
+    right.extend(stdio_lines[stdio_lines.len().saturating_sub(stdio_rows)..].iter().map(|l| String::from(*l)));
+    right.resize(stdio_rows + 2, String::new());
+    right.extend(link);
+
+    let headers = ["Radio", "STDIO", "Link"];
+    let style = |line: String| match headers.contains(&line.trim_end()) {
+        true => line.yellow().bold().to_string(),
+        false => line,
+    };
+    let mut screen = String::from("\x1b[H");
+    for row in 0..body_rows {
+        let l = left.get(row).map(|l| l.as_str()).unwrap_or("");
+        let r = right.get(row).map(|r| r.as_str()).unwrap_or("");
+        screen.push_str(&format!("{} │ {}\x1b[K\n", style(fit(l, left_width)), style(fit(r, right_width))));
+    }
+    screen.push_str(&format!("\x1b[K{}", "q: quit".dimmed()));
+    screen
+}
+
+/// Run the dashboard over the current link until the user quits
+pub fn run(interval: Duration) -> io::Result<()> {
+    let _raw = RawMode::enable()?;
+    // Restores the terminal on any exit, errors and panics included
+    let _screen = AlternateScreen::enter()?;
+    let mut out = io::stdout();
+    let mut history = History::default();
+    let started = Instant::now();
+    Link::set_stdio_handler(Some(capture_stdio));
+    let result = loop {
+        let telemetry = Telemetry::poll();
+        history.push(&telemetry);
+        let link = Link::acquire();
+        let stats = link.stats();
+        link.release();
+        let screen = render(&telemetry, &history, &stats, started.elapsed());
+        if let Err(e) = out.write_all(screen.as_bytes()).and_then(|_| out.flush()) {
+            break Err(e);
+        }
+        // A bare Esc cannot be told apart from the start of an arrow key
+        match term::read_key(interval) {
+            Ok(Some(b'q')) | Ok(Some(0x03)) => break Ok(()),
+            Ok(_) => (),
+            Err(e) => break Err(e),
+        }
+    };
+    Link::set_stdio_handler(None);
+    result
+}
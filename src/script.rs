@@ -0,0 +1,198 @@
+//! This module handles the execution of batch scripts of rtxlink commands
+
+/*!
+A script contains one command per line, empty lines and everything following
+a `#` are ignored. The whole script is parsed before the first command is
+sent, execution stops at the first failing command. Since the radio reboots
+after writing a memory, `flash` can only be the last command of a script. It
+refuses raw images and backups of another radio or memory, unless followed by
+`force`.
+
+```text
+| Command                 | Action                                       |
+|:-----------------------:|:--------------------------------------------:|
+| get PARAM               | Print freqrx, freqtx, time, gps or info      |
+| set freqrx FREQ         | Set the receive frequency                    |
+| set freqtx FREQ         | Set the transmit frequency                   |
+| set time sync           | Set the radio clock to host UTC              |
+| tune RX [OFFSET]        | Set Rx and Tx frequency, Tx = Rx + OFFSET    |
+| put LOCAL REMOTE        | Upload a file to the radio filesystem        |
+| flash MEM_IDX FILE      | Write an image to a memory of the radio      |
+| wait DURATION           | Pause, e.g. 500ms or 2s (default seconds)    |
+| assert freqrx FREQ      | Fail unless the receive frequency matches    |
+| assert freqtx FREQ      | Fail unless the transmit frequency matches   |
+| assert model MODEL      | Fail unless the radio model matches          |
+| echo TEXT               | Print a message                              |
+```
+*/
+
+use std::io::{Error, ErrorKind, Result};
+use std::thread::sleep;
+use std::time::Duration;
+
+use crate::bandplan;
+use crate::bandplan::BandPlan;
+use crate::cat;
+use crate::flow;
+use crate::fmp;
+use crate::freq::{parse_offset, Frequency};
+
+pub enum Command {
+    Info,
+    GetFreq(bool),
+    GetTime,
+    GetGps,
+    SetFreq(bool, Frequency),
+    SyncTime,
+    Tune(Frequency, Frequency),
+    Put(String, String),
+    Flash(usize, String, bool),
+    Wait(Duration),
+    AssertFreq(bool, Frequency),
+    AssertModel(String),
+    Echo(String),
+}
+
+/// A command together with the script line it comes from
+pub struct Statement {
+    pub line: usize,
+    pub command: Command,
+}
+
+/// Parse a direction parameter, returning whether it refers to transmission
+fn parse_direction(param: &str) -> std::result::Result<bool, String> {
+    match param {
+        "freqrx" => Ok(false),
+        "freqtx" => Ok(true),
+        _ => Err(format!("unknown frequency parameter \"{param}\"")),
+    }
+}
+
+fn parse_freq(freq: &str) -> std::result::Result<Frequency, String> {
+    freq.parse().map_err(|e| format!("invalid frequency \"{freq}\": {e}"))
+}
+
+/// Parse a duration such as 500ms or 2s, plain numbers are seconds
+fn parse_duration(duration: &str) -> std::result::Result<Duration, String> {
+    let (value, scale) = match duration.strip_suffix("ms") {
+        Some(ms) => (ms, 1),
+        None => (duration.strip_suffix('s').unwrap_or(duration), 1000),
+    };
+    value.parse::<u64>()
+         .map_err(|_| format!("invalid duration \"{duration}\""))?
+         .checked_mul(scale)
+         .map(Duration::from_millis)
+         .ok_or(format!("duration \"{duration}\" too long"))
+}
+
+/// Parse a single script line, returning None for empty lines
+pub fn parse_line(line: &str) -> std::result::Result<Option<Command>, String> {
+    let line = line.split('#').next().unwrap_or("").trim();
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let command = match words.as_slice() {
+        [] => return Ok(None),
+        ["get", "info"] => Command::Info,
+        ["get", "time"] => Command::GetTime,
+        ["get", "gps"] => Command::GetGps,
+        ["get", param] => Command::GetFreq(parse_direction(param)?),
+        ["set", "time", "sync"] => Command::SyncTime,
+        ["set", param, freq] => Command::SetFreq(parse_direction(param)?, parse_freq(freq)?),
+        ["tune", rx] => {
+            let rx = parse_freq(rx)?;
+            Command::Tune(rx, rx)
+        },
+        ["tune", rx, offset] => {
+            let rx = parse_freq(rx)?;
+            let offset = parse_offset(offset).map_err(|e| format!("invalid offset \"{offset}\": {e}"))?;
+            let tx = rx.offset(offset).ok_or(format!("offset {offset} Hz from {rx} is out of range"))?;
+            Command::Tune(rx, tx)
+        },
+        ["put", local, remote] => Command::Put(String::from(*local), String::from(*remote)),
+        ["flash", mem, file, force @ ..] if matches!(force, [] | ["force"]) => {
+            let mem = mem.parse().map_err(|_| format!("invalid memory index \"{mem}\""))?;
+            Command::Flash(mem, String::from(*file), !force.is_empty())
+        },
+        ["wait", duration] => Command::Wait(parse_duration(duration)?),
+        ["assert", "model", model @ ..] if !model.is_empty() => Command::AssertModel(model.join(" ")),
+        ["assert", param, freq] => Command::AssertFreq(parse_direction(param)?, parse_freq(freq)?),
+        ["echo", text @ ..] => Command::Echo(text.join(" ")),
+        _ => return Err(format!("invalid command \"{line}\"")),
+    };
+    Ok(Some(command))
+}
+
+/// Parse a script, reporting the first invalid line
+pub fn parse(name: &str, text: &str) -> Result<Vec<Statement>> {
+    let mut statements = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let parsed = match statements.last() {
+            Some(Statement { line: flash, command: Command::Flash(..) }) => match parse_line(line) {
+                Ok(None) => Ok(None),
+                _ => Err(format!("the radio reboots after flash on line {flash}, it must be the last command")),
+            },
+            _ => parse_line(line),
+        };
+        match parsed {
+            Ok(Some(command)) => statements.push(Statement { line: i + 1, command }),
+            Ok(None) => (),
+            Err(why) => return Err(Error::new(ErrorKind::InvalidData,
+                                              format!("{}:{}: {}", name, i + 1, why))),
+        }
+    }
+    Ok(statements)
+}
+
+/// Execute a single command
+pub fn execute(command: &Command, plan: Option<&BandPlan>) -> Result<()> {
+    match command {
+        Command::Info => println!("{}", cat::info()?),
+        Command::GetFreq(is_tx) => {
+            let freq = cat::get_freq(*is_tx)?;
+            println!("{}: {freq}", if *is_tx { "Tx" } else { "Rx" });
+        },
+        Command::GetTime => println!("{}", cat::get_time()?.format("%Y-%m-%d %H:%M:%S UTC")),
+        Command::GetGps => println!("{}", cat::gps()?),
+        Command::SetFreq(is_tx, freq) => {
+            if *is_tx {
+                bandplan::check_tx(*freq, plan)?;
+            }
+            cat::set_freq(*freq, *is_tx)?;
+        },
+        Command::SyncTime => cat::set_time(chrono::Utc::now())?,
+        Command::Tune(rx, tx) => {
+            bandplan::check_tx(*tx, plan)?;
+            flow::tune(*rx, *tx)?;
+        },
+        Command::Put(local, remote) => fmp::write_file(local, remote, None)?,
+        Command::Flash(mem, file, force) => {
+            let options = flow::RestoreOptions { verify: true, force: *force, ..Default::default() };
+            flow::restore(Some(mem.to_string()), Some(file.clone()), &options, None)?;
+        },
+        Command::Wait(duration) => sleep(*duration),
+        Command::AssertFreq(is_tx, expected) => {
+            let freq = cat::get_freq(*is_tx)?;
+            if freq != *expected {
+                return Err(Error::other(format!("expected {expected}, radio reports {freq}")));
+            }
+        },
+        Command::AssertModel(expected) => {
+            let info = cat::info()?;
+            if info.model != *expected {
+                return Err(Error::other(format!("expected model {expected}, radio is {}", info.model)));
+            }
+        },
+        Command::Echo(text) => println!("{text}"),
+    };
+    Ok(())
+}
+
+/// Execute a parsed script over the current link, stopping at the first
+/// failure; transmit frequencies are checked against the band plan if given
+pub fn run(name: &str, statements: &[Statement], plan: Option<&BandPlan>) -> Result<()> {
+    for statement in statements {
+        execute(&statement.command, plan).map_err(|e| {
+            Error::new(e.kind(), format!("{}:{}: {}", name, statement.line, e))
+        })?;
+    }
+    Ok(())
+}
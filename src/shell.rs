@@ -0,0 +1,305 @@
+//! This module handles the interactive rtxlink shell
+
+/*!
+The shell accepts the same commands as batch scripts (see [`crate::script`])
+plus `help`, `history` and `exit`, keeping the serial link open for the whole
+session. Text printed by the radio over STDIO is shown as it arrives.
+
+When attached to a terminal, lines can be edited with the arrow keys, Home,
+End, Ctrl-A, Ctrl-E and Ctrl-U, Up and Down browse the history and Tab
+completes command and parameter names. History is kept across sessions in
+`~/.rtxlink_history`.
+*/
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use text_colorizer::*;
+
+use crate::bandplan::BandPlan;
+use crate::link::Link;
+use crate::script;
+use crate::term;
+use crate::term::RawMode;
+
+const PROMPT: &str = "rtxlink> ";
+
+/// Maximum number of lines kept in the history file
+const HISTORY_SIZE: usize = 500;
+
+/// Interval between checks for STDIO traffic while waiting for input
+const IDLE_POLL: Duration = Duration::from_millis(50);
+
+/// Words accepted after each command, for completion
+const COMMANDS: [(&str, &[&str]); 12] = [
+    ("get", &["freqrx", "freqtx", "time", "gps", "info"]),
+    ("set", &["freqrx", "freqtx", "time"]),
+    ("tune", &[]),
+    ("put", &[]),
+    ("flash", &[]),
+    ("wait", &[]),
+    ("assert", &["freqrx", "freqtx", "model"]),
+    ("echo", &[]),
+    ("help", &[]),
+    ("history", &[]),
+    ("exit", &[]),
+    ("quit", &[]),
+];
+
+enum Input {
+    Line(String),
+    Eof,
+}
+
+/// Print text received over STDIO
+fn print_stdio(data: &[u8]) {
+    print!("{}", String::from_utf8_lossy(data).cyan());
+    let _ = io::stdout().flush();
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".rtxlink_history"))
+}
+
+/// Candidate completions for the last word of a partial line
+pub(crate) fn completions(line: &str) -> Vec<&'static str> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let partial = if line.ends_with(' ') { "" } else { words.last().copied().unwrap_or("") };
+    let position = if line.ends_with(' ') { words.len() } else { words.len().saturating_sub(1) };
+    let candidates: Vec<&'static str> = match position {
+        0 => COMMANDS.iter().map(|(c, _)| *c).collect(),
+        1 => COMMANDS.iter()
+                     .find(|(c, _)| *c == words[0])
+                     .map(|(_, p)| p.to_vec())
+                     .unwrap_or_default(),
+        2 if words[0] == "set" && words[1] == "time" => vec!["sync"],
+        _ => vec![],
+    };
+    candidates.into_iter().filter(|c| c.starts_with(partial)).collect()
+}
+
+/// Longest common prefix of a set of words
+pub(crate) fn common_prefix<'a>(words: &[&'a str]) -> &'a str {
+    let first = words[0];
+    let len = words.iter()
+                   .map(|w| first.bytes().zip(w.bytes()).take_while(|(a, b)| a == b).count())
+                   .min()
+                   .unwrap_or(0);
+    &first[..len]
+}
+
+struct Editor {
+    history: Vec<String>,
+}
+
+impl Editor {
+    fn new() -> Editor {
+        let history = history_path().and_then(|p| fs::read_to_string(p).ok())
+                                    .map(|h| h.lines().map(String::from).collect())
+                                    .unwrap_or_default();
+        Editor { history }
+    }
+
+    fn save_history(&self) {
+        if let Some(path) = history_path() {
+            let start = self.history.len().saturating_sub(HISTORY_SIZE);
+            let mut text = self.history[start..].join("\n");
+            text.push('\n');
+            let _ = fs::write(path, text);
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if !line.is_empty() && self.history.last().map(|l| l.as_str()) != Some(line) {
+            self.history.push(String::from(line));
+        }
+    }
+
+    fn redraw(line: &[u8], cursor: usize) {
+        let mut out = io::stdout();
+        let _ = write!(out, "\r\x1b[K{}{}", PROMPT.green().bold(), String::from_utf8_lossy(line));
+        if cursor < line.len() {
+            let _ = write!(out, "\x1b[{}D", line.len() - cursor);
+        }
+        let _ = out.flush();
+    }
+
+    /// Show STDIO traffic received while waiting for user input, only whole
+    /// frames are read so that the prompt never waits for the rest of one
+    fn idle(line: &[u8], cursor: usize) {
+        let mut link = Link::acquire();
+        let received = match link.frame_ready() {
+            Ok(true) => Some(link.receive().map_err(io::Error::from)),
+            Ok(false) => None,
+            Err(e) => Some(Err(e)),
+        };
+        link.release();
+        if let Some(received) = received {
+            print!("\r\x1b[K");
+            // The STDIO handler prints frame contents on reception
+            match received {
+                Ok(_) => println!(),
+                Err(e) => println!("{}: skipped bad frame: {e}", "Error".red().bold()),
+            }
+            Editor::redraw(line, cursor);
+        }
+    }
+
+    /// Read a line without editing capabilities, when not on a terminal
+    fn read_plain(&mut self) -> io::Result<Input> {
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line)? {
+            0 => Ok(Input::Eof),
+            _ => Ok(Input::Line(String::from(line.trim_end()))),
+        }
+    }
+
+    /// Read a line with editing, history and completion
+    fn read_line(&mut self) -> io::Result<Input> {
+        if !term::is_interactive() {
+            return self.read_plain();
+        }
+        let _raw = RawMode::enable()?;
+        let mut line: Vec<u8> = Vec::new();
+        let mut cursor = 0;
+        let mut hist_idx = self.history.len();
+        let mut last_tab = false;
+        Editor::redraw(&line, cursor);
+        loop {
+            let key = match term::read_key(IDLE_POLL)? {
+                Some(key) => key,
+                None => { Editor::idle(&line, cursor); continue },
+            };
+            let tab = key == b'\t';
+            match key {
+                b'\r' | b'\n' => {
+                    println!();
+                    return Ok(Input::Line(String::from_utf8_lossy(&line).into_owned()));
+                },
+                // Ctrl-C
+                0x03 => {
+                    println!("^C");
+                    return Ok(Input::Line(String::new()));
+                },
+                // Ctrl-D
+                0x04 if line.is_empty() => {
+                    println!();
+                    return Ok(Input::Eof);
+                },
+                // Backspace
+                0x7f | 0x08 if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                },
+                // Ctrl-A, Ctrl-E, Ctrl-U
+                0x01 => cursor = 0,
+                0x05 => cursor = line.len(),
+                0x15 => { line.clear(); cursor = 0 },
+                b'\t' => {
+                    let prefix = String::from_utf8_lossy(&line[..cursor]).into_owned();
+                    let candidates = completions(&prefix);
+                    let partial = if prefix.ends_with(' ') { "" } else { prefix.split_whitespace().last().unwrap_or("") };
+                    if candidates.len() == 1 {
+                        let completion = format!("{} ", &candidates[0][partial.len()..]);
+                        line.splice(cursor..cursor, completion.bytes());
+                        cursor += completion.len();
+                    } else if !candidates.is_empty() {
+                        let common = &common_prefix(&candidates)[partial.len()..];
+                        line.splice(cursor..cursor, common.bytes());
+                        cursor += common.len();
+                        // List the candidates on the second consecutive Tab
+                        if common.is_empty() && last_tab {
+                            println!("\r\n{}", candidates.join("  "));
+                        }
+                    }
+                },
+                // Escape sequences for arrows, Home, End and Delete
+                0x1b => {
+                    let seq = [term::read_key(IDLE_POLL)?, term::read_key(IDLE_POLL)?];
+                    match seq {
+                        [Some(b'['), Some(b'A')] if hist_idx > 0 => {
+                            hist_idx -= 1;
+                            line = self.history[hist_idx].clone().into_bytes();
+                            cursor = line.len();
+                        },
+                        [Some(b'['), Some(b'B')] if hist_idx < self.history.len() => {
+                            hist_idx += 1;
+                            line = self.history.get(hist_idx).cloned().unwrap_or_default().into_bytes();
+                            cursor = line.len();
+                        },
+                        [Some(b'['), Some(b'C')] if cursor < line.len() => cursor += 1,
+                        [Some(b'['), Some(b'D')] if cursor > 0 => cursor -= 1,
+                        [Some(b'['), Some(b'H')] => cursor = 0,
+                        [Some(b'['), Some(b'F')] => cursor = line.len(),
+                        [Some(b'['), Some(b'3')] => {
+                            term::read_key(IDLE_POLL)?;
+                            if cursor < line.len() {
+                                line.remove(cursor);
+                            }
+                        },
+                        _ => (),
+                    }
+                },
+                key if (0x20..0x7f).contains(&key) => {
+                    line.insert(cursor, key);
+                    cursor += 1;
+                },
+                _ => (),
+            }
+            last_tab = tab;
+            Editor::redraw(&line, cursor);
+        }
+    }
+}
+
+fn print_help() {
+    println!("commands:");
+    println!(" get freqrx|freqtx|time|gps|info");
+    println!(" set freqrx|freqtx FREQ");
+    println!(" set time sync");
+    println!(" tune RX [OFFSET]");
+    println!(" put LOCAL REMOTE");
+    println!(" flash MEM_IDX FILE");
+    println!(" wait DURATION");
+    println!(" assert freqrx|freqtx FREQ");
+    println!(" assert model MODEL");
+    println!(" echo TEXT");
+    println!(" history");
+    println!(" exit");
+}
+
+/// Run an interactive session over the current link; transmit frequencies
+/// are checked against the band plan if given
+pub fn run(plan: Option<&BandPlan>) -> io::Result<()> {
+    let mut editor = Editor::new();
+    Link::set_stdio_handler(Some(print_stdio));
+    println!("{}: type help for the list of commands, exit to quit", "rtxlink".yellow());
+    while let Input::Line(line) = editor.read_line()? {
+        let line = line.trim();
+        editor.add_history(line);
+        match line {
+            "" => (),
+            "exit" | "quit" => break,
+            "help" => print_help(),
+            "history" => {
+                for (i, l) in editor.history.iter().enumerate() {
+                    println!("{:4}  {l}", i + 1);
+                }
+            },
+            line => match script::parse_line(line) {
+                Ok(Some(command)) => {
+                    if let Err(e) = script::execute(&command, plan) {
+                        eprintln!("{}: {e}", "Error".red().bold());
+                    }
+                },
+                Ok(None) => (),
+                Err(e) => eprintln!("{}: {e}", "Error".red().bold()),
+            },
+        }
+    }
+    Link::set_stdio_handler(None);
+    editor.save_history();
+    Ok(())
+}
@@ -0,0 +1,262 @@
+//! This module handles the synchronisation of a host directory with a
+//! directory of the radio filesystem
+
+/*!
+Both trees are listed first and compared entry by entry: files missing on
+the destination or differing in size are transferred. Files of equal size are
+considered identical, unless `--checksum` is given: they are then compared by
+CRC32, which requires reading back the whole copy stored on the radio.
+
+With `--push` the radio is made to match the host and with `--pull` the host
+is made to match the radio, `--delete` also removes what only exists on the
+destination. The source directory must exist, the destination is created if
+missing. Without a direction files missing on either side are copied,
+while files differing on both sides are reported as conflicts and skipped,
+since the radio filesystem does not keep modification times. Symbolic links
+on the host are skipped.
+*/
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::checksum;
+use crate::fmp;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+    PUSH,   // Make the radio match the host
+    PULL,   // Make the host match the radio
+    BOTH,   // Copy what is missing on either side
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub is_dir: bool,
+    pub size: u64,
+}
+
+/// Entries of a directory tree by path relative to its root, with / separators
+pub type Tree = BTreeMap<String, Entry>;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    Upload(String),
+    Download(String),
+    MkdirRemote(String),
+    MkdirLocal(String),
+    DeleteRemote(String, bool),
+    DeleteLocal(String, bool),
+    Conflict(String),
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Upload(path) => write!(f, "upload    {path}"),
+            Action::Download(path) => write!(f, "download  {path}"),
+            Action::MkdirRemote(path) | Action::MkdirLocal(path) => write!(f, "mkdir     {path}/"),
+            Action::DeleteRemote(path, is_dir) | Action::DeleteLocal(path, is_dir) => {
+                write!(f, "delete    {path}{}", if *is_dir { "/" } else { "" })
+            },
+            Action::Conflict(path) => write!(f, "conflict  {path} differs on both sides, skipped"),
+        }
+    }
+}
+
+/// Actions copying an entry to the other side
+fn copy(path: &str, is_dir: bool, to_remote: bool) -> Action {
+    let path = String::from(path);
+    match (is_dir, to_remote) {
+        (true, true) => Action::MkdirRemote(path),
+        (true, false) => Action::MkdirLocal(path),
+        (false, true) => Action::Upload(path),
+        (false, false) => Action::Download(path),
+    }
+}
+
+fn delete(path: &str, is_dir: bool, remote: bool) -> Action {
+    match remote {
+        true => Action::DeleteRemote(String::from(path), is_dir),
+        false => Action::DeleteLocal(String::from(path), is_dir),
+    }
+}
+
+/// Check whether a path lies inside a directory already deleted or in conflict
+fn skipped(path: &str, actions: &[Action]) -> bool {
+    actions.iter().any(|a| match a {
+        Action::DeleteRemote(dir, true) | Action::DeleteLocal(dir, true) | Action::Conflict(dir) => {
+            path.starts_with(&format!("{dir}/"))
+        },
+        _ => false,
+    })
+}
+
+/// Compute the actions needed to synchronise two trees; files of equal size
+/// are compared with the given function, returning whether they differ
+pub fn plan(local: &Tree, remote: &Tree, direction: Direction, delete_extra: bool,
+            mut differs: impl FnMut(&str) -> Result<bool>) -> Result<Vec<Action>> {
+    let (src, dst) = match direction {
+        Direction::PULL => (remote, local),
+        _ => (local, remote),
+    };
+    let to_remote = direction != Direction::PULL;
+    let mut actions = Vec::new();
+    for (path, entry) in src {
+        // Entries inside a directory in conflict are left alone
+        if skipped(path, &actions) {
+            continue;
+        }
+        match dst.get(path) {
+            None => actions.push(copy(path, entry.is_dir, to_remote)),
+            Some(other) if other.is_dir != entry.is_dir => {
+                // A file on one side and a directory on the other
+                if direction == Direction::BOTH {
+                    actions.push(Action::Conflict(path.clone()));
+                } else {
+                    actions.push(delete(path, other.is_dir, to_remote));
+                    actions.push(copy(path, entry.is_dir, to_remote));
+                }
+            },
+            Some(_) if entry.is_dir => (),
+            Some(other) => {
+                if other.size != entry.size || differs(path)? {
+                    match direction {
+                        Direction::BOTH => actions.push(Action::Conflict(path.clone())),
+                        _ => actions.push(copy(path, false, to_remote)),
+                    }
+                }
+            },
+        }
+    }
+    for (path, entry) in dst.iter().filter(|(path, _)| !src.contains_key(*path)) {
+        if skipped(path, &actions) {
+            continue;
+        }
+        if direction == Direction::BOTH {
+            actions.push(copy(path, entry.is_dir, false));
+        } else if delete_extra {
+            actions.push(delete(path, entry.is_dir, to_remote));
+        }
+    }
+    Ok(actions)
+}
+
+fn walk_local(dir: &Path, prefix: &str, tree: &mut Tree) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = if prefix.is_empty() { name } else { format!("{prefix}/{name}") };
+        // Symbolic links are not followed, they could loop
+        let meta = fs::symlink_metadata(entry.path())?;
+        if meta.file_type().is_symlink() {
+            continue;
+        }
+        tree.insert(path.clone(), Entry { is_dir: meta.is_dir(), size: if meta.is_dir() { 0 } else { meta.len() } });
+        if meta.is_dir() {
+            walk_local(&entry.path(), &path, tree)?;
+        }
+    }
+    Ok(())
+}
+
+/// List a host directory recursively
+pub fn local_tree(root: &Path) -> Result<Tree> {
+    if !fs::metadata(root)?.is_dir() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} is not a directory", root.display())));
+    }
+    let mut tree = Tree::new();
+    walk_local(root, "", &mut tree)?;
+    Ok(tree)
+}
+
+fn walk_remote(dir: &str, prefix: &str, tree: &mut Tree) -> Result<()> {
+    for entry in fmp::list(dir)? {
+        let path = if prefix.is_empty() { entry.name.clone() } else { format!("{prefix}/{}", entry.name) };
+        tree.insert(path.clone(), Entry { is_dir: entry.is_dir(), size: entry.size as u64 });
+        if entry.is_dir() {
+            walk_remote(&fmp::join_path(dir, &entry.name), &path, tree)?;
+        }
+    }
+    Ok(())
+}
+
+/// List a radio directory recursively
+pub fn remote_tree(root: &str) -> Result<Tree> {
+    let mut tree = Tree::new();
+    walk_remote(root, "", &mut tree).map(|_| tree)
+}
+
+/// List a tree to synchronise: the source must exist, while a missing
+/// destination is empty and is created
+fn tree(list: Result<Tree>, root: &str, is_source: bool) -> Result<Tree> {
+    match list {
+        Err(e) if e.kind() == ErrorKind::NotFound && !is_source => Ok(Tree::new()),
+        result => result.map_err(|e| Error::new(e.kind(), format!("{root}: {e}"))),
+    }
+}
+
+/// Compare a host file with its copy on the radio, reading the latter back
+fn remote_differs(local: &Path, remote: &str) -> Result<bool> {
+    let temp = env::temp_dir().join(format!("rtxlink-sync-{}.tmp", std::process::id()));
+    let temp_name = temp.to_string_lossy().into_owned();
+    let result = fmp::read_file(remote, &temp_name, None).and_then(|_| {
+        Ok(checksum::crc32_file(&temp)? != checksum::crc32_file(local)?)
+    });
+    let _ = fs::remove_file(&temp);
+    result
+}
+
+fn execute(action: &Action, local: &Path, remote: &str) -> Result<()> {
+    let local_path = |path: &str| local.join(path).to_string_lossy().into_owned();
+    match action {
+        Action::Upload(path) => fmp::write_file(&local_path(path), &fmp::join_path(remote, path), None),
+        Action::Download(path) => fmp::read_file(&fmp::join_path(remote, path), &local_path(path), None),
+        Action::MkdirRemote(path) => fmp::mkdir(&fmp::join_path(remote, path)),
+        Action::MkdirLocal(path) => fs::create_dir(local.join(path)),
+        Action::DeleteRemote(path, true) => fmp::remove_all(&fmp::join_path(remote, path)),
+        Action::DeleteRemote(path, false) => fmp::remove(&fmp::join_path(remote, path)),
+        Action::DeleteLocal(path, true) => fs::remove_dir_all(local.join(path)),
+        Action::DeleteLocal(path, false) => fs::remove_file(local.join(path)),
+        Action::Conflict(_) => Ok(()),
+    }
+}
+
+/// Synchronise a host directory with a radio directory, printing every
+/// action; with dry run nothing is changed on either side, with checksum
+/// files of equal size are also compared by content
+pub fn run(local: &Path, remote: &str, direction: Direction, delete_extra: bool, dry_run: bool,
+           checksum: bool) -> Result<()> {
+    if delete_extra && direction == Direction::BOTH {
+        return Err(Error::new(ErrorKind::InvalidInput, "--delete requires --push or --pull"));
+    }
+    // Without a direction both sides are sources, but nothing is deleted
+    let local_files = tree(local_tree(local), &local.to_string_lossy(), direction == Direction::PUSH)?;
+    let remote_files = tree(remote_tree(remote), remote, direction == Direction::PULL)?;
+    let actions = plan(&local_files, &remote_files, direction, delete_extra, |path| match checksum {
+        true => remote_differs(&local.join(path), &fmp::join_path(remote, path)),
+        false => Ok(false),
+    })?;
+    if actions.is_empty() {
+        println!("Already in sync");
+        return Ok(());
+    }
+    if !dry_run {
+        if direction != Direction::PUSH {
+            fs::create_dir_all(local)?;
+        }
+        if direction != Direction::PULL {
+            fmp::mkdir_all(remote)?;
+        }
+    }
+    for action in &actions {
+        println!("{action}");
+        if !dry_run {
+            execute(action, local, remote).map_err(|e| Error::new(e.kind(), format!("{action}: {e}")))?;
+        }
+    }
+    Ok(())
+}
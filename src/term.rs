@@ -0,0 +1,129 @@
+//! This module handles the terminal used by the interactive commands
+
+use std::io::{self, Write};
+use std::time::Duration;
+
+#[cfg(unix)]
+mod imp {
+    use nix::poll::{poll, PollFd, PollFlags};
+    use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, OutputFlags, SetArg, Termios};
+    use nix::unistd::{isatty, read};
+    use std::io;
+    use std::time::Duration;
+
+    const STDIN: i32 = 0;
+    const STDOUT: i32 = 1;
+
+    /// Terminal in raw mode, previous settings are restored when dropped
+    pub struct RawMode {
+        saved: Termios,
+    }
+
+    impl RawMode {
+        pub fn enable() -> io::Result<RawMode> {
+            let saved = tcgetattr(STDIN)?;
+            let mut raw = saved.clone();
+            cfmakeraw(&mut raw);
+            // Keep newline translation so that output can be printed as usual
+            raw.output_flags |= OutputFlags::OPOST;
+            tcsetattr(STDIN, SetArg::TCSANOW, &raw)?;
+            Ok(RawMode { saved })
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            let _ = tcsetattr(STDIN, SetArg::TCSANOW, &self.saved);
+        }
+    }
+
+    pub fn is_interactive() -> bool {
+        isatty(STDIN).unwrap_or(false) && isatty(STDOUT).unwrap_or(false)
+    }
+
+    pub fn size() -> Option<(usize, usize)> {
+        let mut ws = nix::libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        let ret = unsafe { nix::libc::ioctl(STDOUT, nix::libc::TIOCGWINSZ, &mut ws) };
+        match ret == 0 && ws.ws_col > 0 && ws.ws_row > 0 {
+            true => Some((ws.ws_col as usize, ws.ws_row as usize)),
+            false => None,
+        }
+    }
+
+    pub fn read_key(timeout: Duration) -> io::Result<Option<u8>> {
+        let mut fds = [PollFd::new(STDIN, PollFlags::POLLIN)];
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+        if poll(&mut fds, timeout)? == 0 {
+            return Ok(None);
+        }
+        let mut key = [0u8; 1];
+        match read(STDIN, &mut key)? {
+            0 => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            _ => Ok(Some(key[0])),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::io;
+    use std::time::Duration;
+
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> io::Result<RawMode> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "raw terminal mode not supported"))
+        }
+    }
+
+    pub fn is_interactive() -> bool {
+        false
+    }
+
+    pub fn size() -> Option<(usize, usize)> {
+        None
+    }
+
+    pub fn read_key(_timeout: Duration) -> io::Result<Option<u8>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "raw terminal mode not supported"))
+    }
+}
+
+pub use imp::RawMode;
+
+/// Alternate screen with hidden cursor, the normal screen and the cursor are
+/// restored when dropped
+pub struct AlternateScreen;
+
+impl AlternateScreen {
+    pub fn enter() -> io::Result<AlternateScreen> {
+        let mut out = io::stdout();
+        write!(out, "\x1b[?1049h\x1b[?25l\x1b[2J")?;
+        out.flush()?;
+        Ok(AlternateScreen)
+    }
+}
+
+impl Drop for AlternateScreen {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        let _ = write!(out, "\x1b[?25h\x1b[?1049l");
+        let _ = out.flush();
+    }
+}
+
+/// Check whether both standard input and output are attached to a terminal
+pub fn is_interactive() -> bool {
+    imp::is_interactive()
+}
+
+/// Terminal width and height in characters, 80x24 if unknown
+pub fn size() -> (usize, usize) {
+    imp::size().unwrap_or((80, 24))
+}
+
+/// Wait for a key press in raw mode, returning None after the timeout
+pub fn read_key(timeout: Duration) -> io::Result<Option<u8>> {
+    imp::read_key(timeout)
+}
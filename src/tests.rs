@@ -1,8 +1,771 @@
-#[cfg(test)]
+#![cfg(test)]
 
-//use super::*;
+use super::*;
 
 #[test]
 fn example() {
     assert_eq!(1, 1);
 }
+
+#[test]
+fn freq_parse_units() {
+    use freq::Frequency;
+    let hz = |s: &str| s.parse::<Frequency>().map(|f| f.hz());
+    assert_eq!(hz("430.0125"), Ok(430_012_500));
+    assert_eq!(hz("430.0125 MHz"), Ok(430_012_500));
+    assert_eq!(hz("145500k"), Ok(145_500_000));
+    assert_eq!(hz("145500 kHz"), Ok(145_500_000));
+    assert_eq!(hz("145.500.000"), Ok(145_500_000));
+    assert_eq!(hz("1.2975G"), Ok(1_297_500_000));
+    assert_eq!(hz("433920000Hz"), Ok(433_920_000));
+    assert_eq!(hz("144.800000000"), Ok(144_800_000));
+}
+
+#[test]
+fn freq_parse_errors() {
+    use freq::{Frequency, ParseFrequencyError};
+    let err = |s: &str| s.parse::<Frequency>().unwrap_err();
+    assert_eq!(err(""), ParseFrequencyError::Empty);
+    assert_eq!(err("MHz"), ParseFrequencyError::Empty);
+    assert_eq!(err("14x.5"), ParseFrequencyError::InvalidUnit(String::from("x.5")));
+    assert_eq!(err("145.5 furlongs"), ParseFrequencyError::InvalidUnit(String::from("furlongs")));
+    assert_eq!(err("145.50.000"), ParseFrequencyError::InvalidGrouping);
+    assert_eq!(err("145.5000001"), ParseFrequencyError::TooPrecise);
+    assert_eq!(err("10G"), ParseFrequencyError::OutOfRange);
+    assert_eq!(err("-145.5"), ParseFrequencyError::InvalidDigit);
+}
+
+#[test]
+fn freq_round_trip_channel_steps() {
+    use freq::Frequency;
+    for step in [6_250, 12_500, 25_000] {
+        for channel in 0..400 {
+            let freq = Frequency::from_hz(430_000_000 + channel * step);
+            let canonical = freq.to_string();
+            assert_eq!(canonical.parse::<Frequency>(), Ok(freq), "{canonical}");
+        }
+    }
+    assert_eq!(Frequency::from_hz(430_012_500).to_string(), "430.0125 MHz");
+    assert_eq!(Frequency::from_hz(430_006_250).to_string(), "430.00625 MHz");
+    assert_eq!(Frequency::from_hz(145_000_000).to_string(), "145 MHz");
+}
+
+#[test]
+fn bandplan_lookup() {
+    use bandplan::BandPlan;
+    use freq::Frequency;
+    let mhz = |s: &str| s.parse::<Frequency>().unwrap();
+    let region_1 = BandPlan::iaru(Some(1)).unwrap();
+    let region_2 = BandPlan::iaru(Some(2)).unwrap();
+    assert_eq!(region_1.band(mhz("145.5")).unwrap().name, "2m");
+    assert_eq!(region_1.band(mhz("430")).unwrap().name, "70cm");
+    assert!(region_1.band(mhz("147")).is_none());
+    assert_eq!(region_2.band(mhz("147")).unwrap().name, "2m");
+    assert!(BandPlan::iaru(Some(4)).is_none());
+    // Without a region only the allocations shared by all regions are allowed
+    let shared = BandPlan::iaru(None).unwrap();
+    assert_eq!(shared.band(mhz("145.5")).unwrap().name, "2m");
+    assert_eq!(shared.band(mhz("435")).unwrap().name, "70cm");
+    assert_eq!(shared.band(mhz("1.81")).unwrap().to_string(), "160m (1.81 MHz - 2 MHz)");
+    assert!(shared.band(mhz("147")).is_none());
+    assert!(shared.band(mhz("70.2")).is_none());
+    assert!(shared.band(mhz("445")).is_none());
+
+    let custom = BandPlan::parse("custom", "# Local repeaters\n145.6 145.8 R0-R7\n\n430.000.000 431M\n").unwrap();
+    assert_eq!(custom.bands.len(), 2);
+    assert_eq!(custom.band(mhz("145.7")).unwrap().name, "R0-R7");
+    assert_eq!(custom.band(mhz("431")).unwrap().name, "430 MHz - 431 MHz");
+    assert!(custom.band(mhz("145.5")).is_none());
+    assert!(BandPlan::parse("bad", "146M 144M").is_err());
+    assert!(BandPlan::parse("bad", "146M").is_err());
+}
+
+#[test]
+fn freq_parse_offset() {
+    use freq::{parse_offset, Frequency};
+    assert_eq!(parse_offset("+600k"), Ok(600_000));
+    assert_eq!(parse_offset("-7.6M"), Ok(-7_600_000));
+    assert_eq!(parse_offset("5M"), Ok(5_000_000));
+    assert!(parse_offset("+-5M").is_err());
+    let output = Frequency::from_hz(145_600_000);
+    assert_eq!(output.offset(-600_000), Some(Frequency::from_hz(145_000_000)));
+    assert_eq!(output.offset(-200_000_000), None);
+}
+
+#[test]
+fn radio_info_parse() {
+    use info::RadioInfo;
+    let bare = RadioInfo::parse("MD-UV3x0\0");
+    assert_eq!(bare.model, "MD-UV3x0");
+    assert_eq!(bare.firmware, None);
+    assert_eq!(bare.id(), "MD-UV3x0");
+
+    let full = RadioInfo::parse("OpenRTX Module17 rev0.1e v0.3.6-42-gabc1234 sn:M17-0042");
+    assert_eq!(full.model, "Module17");
+    assert_eq!(full.hw_rev.as_deref(), Some("0.1e"));
+    assert_eq!(full.firmware.as_deref(), Some("v0.3.6-42-gabc1234"));
+    assert_eq!(full.git_hash.as_deref(), Some("abc1234"));
+    assert_eq!(full.serial.as_deref(), Some("M17-0042"));
+    assert_eq!(full.id(), "Module17-M170042");
+
+    let tagged = RadioInfo::parse("model=GD-77; hw=1; fw=0.3.5; git=0123abcd");
+    assert_eq!(tagged.model, "GD-77");
+    assert_eq!(tagged.hw_rev.as_deref(), Some("1"));
+    assert_eq!(tagged.git_hash.as_deref(), Some("0123abcd"));
+    assert_eq!(tagged.to_string(), "GD-77 (hw 1, fw 0.3.5, git 0123abcd)");
+
+    let dirty = RadioInfo::parse("MD-380 v0.3.6-42-gabc1234-dirty");
+    assert_eq!(dirty.firmware.as_deref(), Some("v0.3.6-42-gabc1234-dirty"));
+    assert_eq!(dirty.git_hash.as_deref(), Some("abc1234"));
+
+    // Only rev followed by a digit is a hardware revision
+    let revo = RadioInfo::parse("Revo X1 rev-2");
+    assert_eq!(revo.model, "Revo X1");
+    assert_eq!(revo.hw_rev.as_deref(), Some("2"));
+    assert_eq!(RadioInfo::parse("Revision").hw_rev, None);
+
+    assert_eq!(RadioInfo::parse("").model, "unknown");
+    assert_eq!(RadioInfo::parse("Some Radio").id(), "SomeRadio");
+}
+
+#[test]
+fn gps_nmea_sentences() {
+    use gps::GpsInfo;
+    let mut data = vec![1, 3, 8, 12];
+    for value in [45.5f32, -9.25, 120.0, 18.52, 90.0] {
+        data.extend(value.to_le_bytes());
+    }
+    let fix = GpsInfo::from_bytes(&data).unwrap();
+    assert_eq!(fix.satellites, 8);
+    assert_eq!(fix.longitude, -9.25);
+    assert!(GpsInfo::from_bytes(&data[..20]).is_err());
+
+    let time = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let sentences = fix.nmea(time);
+    assert_eq!(sentences[0], "$GPGGA,221320.000,4530.0000,N,00915.0000,W,1,08,,120.0,M,,M,,*7A\r\n");
+    assert_eq!(sentences[1], "$GPRMC,221320.000,A,4530.0000,N,00915.0000,W,10.0,90.0,141123,,,A*7F\r\n");
+    // Minutes rounding up to 60 carry into the degrees
+    assert_eq!(gps::nmea_coordinate(0.99999994, 2, ('N', 'S')), "0100.0000,N");
+    assert_eq!(gps::nmea_coordinate(7.123456, 3, ('E', 'W')), "00707.4074,E");
+}
+
+#[test]
+fn script_parse() {
+    use script::Command;
+    let text = "# Repeater setup\n\
+                set freqrx 430.0125\n\
+                \n\
+                tune 145.6 -600k  # R0\n\
+                wait 500ms\n\
+                assert freqtx 145M\n\
+                assert model MD-UV3x0\n\
+                put codeplug.bin /codeplug.bin\n";
+    let statements = script::parse("setup.txt", text).unwrap();
+    let lines: Vec<usize> = statements.iter().map(|s| s.line).collect();
+    assert_eq!(lines, vec![2, 4, 5, 6, 7, 8]);
+    assert!(matches!(statements[0].command, Command::SetFreq(false, f) if f.hz() == 430_012_500));
+    assert!(matches!(statements[1].command, Command::Tune(rx, tx) if rx.hz() == 145_600_000
+                                                                   && tx.hz() == 145_000_000));
+    assert!(matches!(statements[2].command, Command::Wait(d) if d.as_millis() == 500));
+    assert!(matches!(&statements[4].command, Command::AssertModel(m) if m == "MD-UV3x0"));
+    assert!(matches!(&statements[5].command, Command::Put(l, r) if l == "codeplug.bin" && r == "/codeplug.bin"));
+
+    let err = script::parse("bad.txt", "get freqrx\nset freqrx 430.0125.5\n").err().unwrap();
+    assert!(err.to_string().starts_with("bad.txt:2: invalid frequency"));
+    let err = script::parse("bad.txt", "get freqrx\n\nfrobnicate\n").err().unwrap();
+    assert_eq!(err.to_string(), "bad.txt:3: invalid command \"frobnicate\"");
+    let err = script::parse("bad.txt", "wait 1ms\nwait 18446744073709551615s\n").err().unwrap();
+    assert!(err.to_string().starts_with("bad.txt:2: duration"));
+    // Nothing can follow flash, which reboots the radio
+    assert!(script::parse("ok.txt", "get info\nflash 0 fw.bin\n# done\n").is_ok());
+    assert!(matches!(script::parse("ok.txt", "flash 1 fw.bin force\n").unwrap()[0].command,
+                     script::Command::Flash(1, _, true)));
+    assert!(script::parse("bad.txt", "flash 1 fw.bin now\n").is_err());
+    let err = script::parse("bad.txt", "flash 0 fw.bin\n\nget info\n").err().unwrap();
+    assert!(err.to_string().starts_with("bad.txt:3: the radio reboots after flash on line 1"));
+}
+
+#[test]
+fn cat_event_decode() {
+    use cat::Event;
+    use freq::Frequency;
+    let freq = Event::from_notification(&[b'N', b'R', b'F', 0xA0, 0x83, 0xA4, 0x19]);
+    assert_eq!(freq, Some(Event::FreqRx(Frequency::from_hz(430_212_000))));
+    assert_eq!(Event::from_notification(&[b'N', b'P', b'T', 1]), Some(Event::Ptt(true)));
+    assert_eq!(Event::from_notification(&[b'N', b'S', b'Q', 0]), Some(Event::Squelch(false)));
+    assert_eq!(Event::from_notification(&[b'N', b'C', b'H', 0x2A, 0x00]), Some(Event::Channel(42)));
+    let battery = Event::from_notification(&[b'N', b'B', b'A', 0x6C, 0x0E]).unwrap();
+    assert_eq!(battery.to_string(), "Low battery 3.692 V");
+    // Truncated payload, unknown ID and non-notification opcode
+    assert_eq!(Event::from_notification(&[b'N', b'R', b'F', 0x20]), None);
+    assert_eq!(Event::from_notification(&[b'N', b'X', b'X', 1]), None);
+    assert_eq!(Event::from_notification(&[b'D', b'P', b'T', 1]), None);
+}
+
+#[test]
+fn cat_event_handlers() {
+    use cat::Event;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    // A handler may register another one without deadlocking
+    cat::on_event(Arc::new(|_| {
+        if CALLS.fetch_add(1, Ordering::SeqCst) == 0 {
+            cat::on_event(Arc::new(|_| { CALLS.fetch_add(10, Ordering::SeqCst); }));
+        }
+    }));
+    cat::dispatch(vec![Event::Ptt(true)]);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    cat::dispatch(vec![Event::Ptt(false), Event::Squelch(true)]);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 23);
+}
+
+#[test]
+fn cat_time_drift() {
+    use chrono::{DateTime, Duration};
+    let time = DateTime::from_timestamp(1_714_818_093, 0).unwrap();
+    let data = cat::encode_time(time);
+    assert_eq!(data, [0x2d, 0x0c, 0x36, 0x66, 0, 0, 0, 0]);
+    assert_eq!(cat::decode_time(&data).unwrap(), time);
+    // Trailing bytes are ignored, a short or out of range time is refused
+    assert_eq!(cat::decode_time(&[0x2d, 0x0c, 0x36, 0x66, 0, 0, 0, 0, 0xff]).unwrap(), time);
+    assert!(cat::decode_time(&data[..4]).is_err());
+    assert!(cat::decode_time(&[0xff; 7].into_iter().chain([0x7f]).collect::<Vec<u8>>()).is_err());
+    assert_eq!(cat::drift(time, time + Duration::milliseconds(400)), "none");
+    assert_eq!(cat::drift(time + Duration::seconds(3), time), "radio 3 s ahead");
+    assert_eq!(cat::drift(time, time + Duration::seconds(75)), "radio 75 s behind");
+}
+
+#[test]
+fn hex_parse_dump() {
+    assert_eq!(hex::parse("0x424c"), Ok(vec![0x42, 0x4c]));
+    assert_eq!(hex::parse("de:ad be-ef"), Ok(vec![0xde, 0xad, 0xbe, 0xef]));
+    assert_eq!(hex::parse(""), Ok(vec![]));
+    assert!(hex::parse("abc").is_err());
+    assert!(hex::parse("zz").is_err());
+
+    let dump = hex::dump(b"OpenRTX\x00\x01 rtxlink test!");
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "00000000  4f 70 65 6e 52 54 58 00 01 20 72 74 78 6c 69 6e  |OpenRTX.. rtxlin|");
+    assert!(lines[1].starts_with("00000010  6b 20 74 65 73 74 21 "));
+    assert!(lines[1].ends_with("|k test!|"));
+}
+
+#[test]
+fn fmp_dir_entry_decode() {
+    use fmp::{DirEntry, EntryType};
+    let entry = DirEntry::try_from(&b"\x00\x10\x27\x00\x00settings.bin"[..]).unwrap();
+    assert_eq!(entry, DirEntry { name: String::from("settings.bin"), kind: EntryType::FILE, size: 10000 });
+    let entry = DirEntry::try_from(&b"\x01\x00\x00\x00\x00logs"[..]).unwrap();
+    assert!(entry.is_dir());
+    assert!(DirEntry::try_from(&b"\x02\x00\x00\x00\x00x"[..]).is_err());
+    assert!(DirEntry::try_from(&b"\x00\x00\x00"[..]).is_err());
+    assert_eq!(fmp::join_path("/", "logs"), "/logs");
+    assert_eq!(fmp::join_path("/logs", "a.txt"), "/logs/a.txt");
+}
+
+#[test]
+fn errno_errors() {
+    use link::Errno;
+    use std::io::ErrorKind;
+    assert_eq!(Errno::error(Errno::ENOSPC as u8).kind(), ErrorKind::StorageFull);
+    assert_eq!(Errno::error(Errno::EROFS as u8).kind(), ErrorKind::ReadOnlyFilesystem);
+    assert_eq!(Errno::error(Errno::ENOENT as u8).kind(), ErrorKind::NotFound);
+    assert_eq!(Errno::error(Errno::EBADRQC as u8).to_string(), "EBADRQC: invalid request code");
+    assert_eq!(Errno::error(200).to_string(), "unknown error code 200");
+    // Every known code has its own description
+    let known: Vec<Errno> = (0..=255u8).filter_map(|code| Errno::try_from(code).ok()).collect();
+    assert_eq!(known.len(), 15);
+    let mut descriptions: Vec<&str> = known.iter().map(|errno| errno.describe().1).collect();
+    descriptions.sort();
+    descriptions.dedup();
+    assert_eq!(descriptions.len(), known.len());
+    assert_eq!(Errno::ENOTEMPTY.describe(), (ErrorKind::DirectoryNotEmpty, "directory not empty"));
+    assert_eq!(Errno::EGENERIC.to_string(), "EGENERIC: generic error");
+}
+
+#[test]
+fn fmp_path_trees() {
+    use fmp::{DirEntry, EntryType};
+    assert_eq!(fmp::path_prefixes("/a/b/c"), vec!["/a", "/a/b", "/a/b/c"]);
+    assert_eq!(fmp::path_prefixes("a//b/"), vec!["/a", "/a/b"]);
+    assert!(fmp::path_prefixes("/").is_empty());
+    // Directories are emptied before being removed
+    let entry = |name: &str, kind| DirEntry { name: String::from(name), kind, size: 0 };
+    let list = |path: &str| Ok(match path {
+        "/logs" => vec![entry("a.txt", EntryType::FILE), entry("old", EntryType::DIR)],
+        "/logs/old" => vec![entry("b.txt", EntryType::FILE)],
+        _ => vec![],
+    });
+    let mut removed = Vec::new();
+    fmp::remove_tree("/logs", &list, &mut |path: &str| { removed.push(String::from(path)); Ok(()) }).unwrap();
+    assert_eq!(removed, vec!["/logs/a.txt", "/logs/old/b.txt", "/logs/old", "/logs"]);
+    // The first failure stops the removal
+    let mut count = 0;
+    let failing = fmp::remove_tree("/logs", &list, &mut |_: &str| {
+        count += 1;
+        Err(link::Errno::error(link::Errno::EROFS as u8))
+    });
+    assert_eq!((failing.unwrap_err().kind(), count), (std::io::ErrorKind::ReadOnlyFilesystem, 1));
+}
+
+#[test]
+fn checksum_crc32() {
+    assert_eq!(checksum::crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(checksum::crc32(b""), 0);
+    let mut crc = checksum::Crc32::new();
+    crc.update(b"1234");
+    crc.update(b"56789");
+    assert_eq!(crc.finish(), 0xCBF4_3926);
+    assert_eq!(checksum::Algorithm::CRC32.digest(b"123456789"), vec![0x26, 0x39, 0xf4, 0xcb]);
+    assert_eq!(hex::encode(&checksum::Algorithm::SHA256.digest(b"abc")),
+               "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+}
+
+#[test]
+fn sync_plan() {
+    use sync::{plan, Action, Direction, Entry, Tree};
+    let file = |size| Entry { is_dir: false, size };
+    let dir = Entry { is_dir: true, size: 0 };
+    let local: Tree = [("a.txt", file(10)), ("b.txt", file(20)), ("prompts", dir), ("prompts/1.wav", file(5))]
+        .into_iter().map(|(p, e)| (String::from(p), e)).collect();
+    let remote: Tree = [("a.txt", file(10)), ("b.txt", file(20)), ("old", dir), ("old/x", file(1)), ("c.txt", file(3))]
+        .into_iter().map(|(p, e)| (String::from(p), e)).collect();
+    // Only b.txt differs in content
+    let differs = |p: &str| Ok(p == "b.txt");
+
+    let actions = plan(&local, &remote, Direction::PUSH, true, differs).unwrap();
+    assert_eq!(actions, vec![Action::Upload(String::from("b.txt")),
+                             Action::MkdirRemote(String::from("prompts")),
+                             Action::Upload(String::from("prompts/1.wav")),
+                             Action::DeleteRemote(String::from("c.txt"), false),
+                             Action::DeleteRemote(String::from("old"), true)]);
+
+    let actions = plan(&local, &remote, Direction::PULL, false, differs).unwrap();
+    assert_eq!(actions, vec![Action::Download(String::from("b.txt")),
+                             Action::Download(String::from("c.txt")),
+                             Action::MkdirLocal(String::from("old")),
+                             Action::Download(String::from("old/x"))]);
+
+    let actions = plan(&local, &remote, Direction::BOTH, false, differs).unwrap();
+    assert_eq!(actions[0], Action::Conflict(String::from("b.txt")));
+    assert_eq!(actions.len(), 6);
+
+    assert!(plan(&local, &local, Direction::PUSH, true, |_| Ok(false)).unwrap().is_empty());
+
+    // A directory on one side and a file on the other is skipped whole
+    let remote: Tree = [("prompts", file(7))].into_iter().map(|(p, e)| (String::from(p), e)).collect();
+    let actions = plan(&local, &remote, Direction::BOTH, false, differs).unwrap();
+    assert_eq!(actions, vec![Action::Upload(String::from("a.txt")),
+                             Action::Upload(String::from("b.txt")),
+                             Action::Conflict(String::from("prompts"))]);
+}
+
+#[test]
+fn fmp_meminfo_decode() {
+    use fmp::MemInfo;
+    let mut raw = vec![0x00, 0x00, 0x10, 0x00, 0x4e];
+    raw.extend(b"External flash");
+    raw.resize(fmp::MEMINFO_SIZE, 0);
+    let mem = MemInfo::try_from(raw.as_slice()).unwrap();
+    assert_eq!(mem.name, "External flash");
+    assert_eq!(mem.size, 1024 * 1024);
+    assert!(mem.flags.erasable() && mem.flags.external() && mem.flags.filesystem());
+    assert!(!mem.flags.read_only());
+    assert_eq!(mem.flags.erase_block_size(), Some(4096));
+    assert_eq!(mem.to_string(), "Externalflash_1048576");
+    assert_eq!(mem.flags.to_string(), "external, erasable, filesystem, 4096B erase block");
+
+    // A name filling the whole field has no terminator
+    let mut raw = vec![0x00, 0x04, 0x00, 0x00, 0x01];
+    raw.extend([b'A'; 27]);
+    assert_eq!(MemInfo::try_from(raw.as_slice()).unwrap().name.len(), 27);
+    assert!(MemInfo::try_from(&raw[..20]).is_err());
+}
+
+#[test]
+fn fmp_request_encode() {
+    use fmp::Request;
+    assert_eq!(Request::MEMINFO.encode().unwrap(), vec![0x01, 0x00]);
+    assert_eq!(Request::DUMP { mem: 2, range: None }.encode().unwrap(), vec![0x02, 0x01, 0x01, 0x02]);
+    let range = Some(fmp::MemRange { offset: 0x8000, length: 0x1000 });
+    assert_eq!(Request::FLASH { mem: 1, range }.encode().unwrap(),
+               vec![0x03, 0x03, 0x01, 0x01, 0x04, 0x00, 0x80, 0x00, 0x00, 0x04, 0x00, 0x10, 0x00, 0x00]);
+    assert_eq!(Request::WRITE { path: String::from("/a.txt"), size: 1000 }.encode().unwrap(),
+               vec![0x05, 0x02, 0x06, b'/', b'a', b'.', b't', b'x', b't', 0x04, 0xe8, 0x03, 0x00, 0x00]);
+    assert_eq!(Request::MOVE { from: String::from("/a"), to: String::from("/b") }.encode().unwrap(),
+               vec![0x07, 0x02, 0x02, b'/', b'a', 0x02, b'/', b'b']);
+    let range = fmp::MemRange { offset: 0, length: 0x10000 };
+    assert_eq!(Request::CHECKSUM { mem: 0, range, algorithm: checksum::Algorithm::SHA256 }.encode().unwrap(),
+               vec![0x0b, 0x04, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x01, 0x00, 0x01, 0x01]);
+    assert_eq!(Request::RESET.encode().unwrap(), vec![0xff, 0x00]);
+    // Parameters longer than 255 bytes cannot be represented
+    assert!(Request::RM { path: "x".repeat(256) }.encode().is_err());
+    assert!(fmp::encode(0x06, &vec![vec![]; 256]).is_err());
+}
+
+#[test]
+fn fmp_response_decode() {
+    use fmp::{Opcode, Reply, Response};
+    use std::io::ErrorKind;
+    let reply = Reply::try_from(&[0x04, 0x00, 0x02, 0x01, 0x02, 0xaa, 0xbb, 0xcc][..]).unwrap();
+    assert_eq!(reply.params, vec![vec![0xaa], vec![0xbb, 0xcc]]);
+
+    let read = Response::decode(Opcode::READ, &[0x04, 0x00, 0x01, 0x04, 0x10, 0x27, 0x00, 0x00]).unwrap();
+    assert_eq!(read, Response::READ { size: 10000 });
+    let list = Response::decode(Opcode::LIST, &[0x06, 0x00, 0x01, 0x07, 0x01, 0, 0, 0, 0, b'l', b'g']).unwrap();
+    assert!(matches!(list, Response::LIST(entries) if entries[0].name == "lg" && entries[0].is_dir()));
+    assert_eq!(Response::decode(Opcode::MKDIR, &[0x09, 0x00, 0x00]).unwrap(), Response::MKDIR);
+    let digest = Response::decode(Opcode::CHECKSUM, &[0x0b, 0x00, 0x01, 0x04, 0x26, 0x39, 0xf4, 0xcb]).unwrap();
+    assert_eq!(digest, Response::CHECKSUM(vec![0x26, 0x39, 0xf4, 0xcb]));
+    // Old firmware rejects the command
+    let err = Response::decode(Opcode::CHECKSUM, &[0x0b, 56, 0x00]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+    // Status codes are reported as errors
+    let err = Response::decode(Opcode::WRITE, &[0x05, 28, 0x00]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::StorageFull);
+    // Malformed replies
+    let malformed: [&[u8]; 6] = [
+        &[0x04, 0x00],                          // Truncated header
+        &[0x04, 0x00, 0x02, 0x01],              // Truncated sizes
+        &[0x04, 0x00, 0x01, 0x04, 0x10, 0x27],  // Truncated parameter
+        &[0x09, 0x00, 0x00, 0x00],              // Trailing byte
+        &[0x04, 0x00, 0x01, 0x02, 0x10, 0x27],  // Short file size
+        &[0x01, 0x00, 0x01, 0x02, 0x00, 0x00],  // Short MEMINFO entry
+    ];
+    for data in malformed {
+        let err = Response::decode(Opcode::try_from(data[0]).unwrap(), data).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{data:x?}");
+    }
+    assert!(Response::decode(Opcode::READ, &[0x05, 0x00, 0x00]).is_err());
+}
+
+#[test]
+fn fmp_reset_outcome() {
+    use std::io::{Error, ErrorKind};
+    assert!(fmp::reset_outcome(Ok(())).is_ok());
+    // The radio rebooting before its reply is not an error
+    assert!(fmp::reset_outcome::<()>(Err(Error::from(ErrorKind::TimedOut))).is_ok());
+    let err = fmp::reset_outcome::<()>(Err(Error::from(ErrorKind::BrokenPipe))).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn fmp_mem_range() {
+    use fmp::{MemFlags, MemInfo, MemRange};
+    // 64 KiB memory with 4 KiB erase blocks
+    let mem = MemInfo { size: 0x10000, flags: MemFlags(0x42), name: String::from("Settings") };
+    assert_eq!(MemRange::new(&mem, None, None).unwrap(), MemRange::whole(&mem));
+    assert_eq!(MemRange::new(&mem, Some(0x8000), None).unwrap(), MemRange { offset: 0x8000, length: 0x8000 });
+    let range = MemRange::new(&mem, Some(0x1000), Some(0x2000)).unwrap();
+    assert_eq!(range.to_string(), "0x1000-0x3000");
+    assert!(range.check_erase_blocks(&mem).is_ok());
+    assert!(MemRange::new(&mem, Some(0x100), Some(0x100)).unwrap().check_erase_blocks(&mem).is_err());
+    assert!(MemRange::new(&mem, Some(0x10000), None).is_err());
+    assert!(MemRange::new(&mem, Some(0xf000), Some(0x2000)).is_err());
+    assert!(MemRange::new(&mem, None, Some(0)).is_err());
+}
+
+#[test]
+fn flow_compare_readback() {
+    use flow::{compare, Mismatch};
+    let image: Vec<u8> = (0..64).collect();
+    assert_eq!(compare(&image, &image, 16), None);
+    let mut readback = image.clone();
+    readback[20] = 0xff;
+    readback[21] = 0xff;
+    readback[50] = 0xff;
+    assert_eq!(compare(&image, &readback, 16), Some(Mismatch { offset: 20, bad_blocks: 2 }));
+    // Short read back
+    assert_eq!(compare(&image, &image[..40], 16), Some(Mismatch { offset: 40, bad_blocks: 2 }));
+}
+
+#[test]
+fn flow_merge_blocks() {
+    use flow::merge_blocks;
+    use fmp::MemRange;
+    assert!(merge_blocks(&[], 4096, 0x10000).is_empty());
+    assert_eq!(merge_blocks(&[1, 2, 3, 7], 4096, 0x10000),
+               vec![MemRange { offset: 0x1000, length: 0x3000 }, MemRange { offset: 0x7000, length: 0x1000 }]);
+    // The last block may be shorter
+    assert_eq!(merge_blocks(&[2], 4096, 0x2800), vec![MemRange { offset: 0x2000, length: 0x800 }]);
+}
+
+#[test]
+fn flow_verify_checks() {
+    use checksum::Algorithm;
+    use flow::{check_digest, region_data};
+    use fmp::{MemFlags, MemInfo, MemRange};
+    use std::io::{Error, ErrorKind};
+    let mem = MemInfo { size: 1024, flags: MemFlags(0x02), name: String::from("Internal Flash") };
+    let image: Vec<u8> = (0..1024).map(|i| (i % 251) as u8).collect();
+    let range = MemRange { offset: 0x100, length: 0x80 };
+    // A whole memory image is sliced, a region image is taken whole
+    assert_eq!(region_data(&mem, &image, range).unwrap(), &image[0x100..0x180]);
+    assert_eq!(region_data(&mem, &image[..0x80], range).unwrap(), &image[..0x80]);
+    assert_eq!(region_data(&mem, &image[..0x81], range).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    let data = &image[..0x80];
+    let crc = Algorithm::CRC32.digest(data);
+    let mut readbacks = 0;
+    assert!(check_digest(Ok(crc.clone()), data, || { readbacks += 1; Ok(()) }).is_ok());
+    assert_eq!(readbacks, 0);
+    // A checksum mismatch fails even if the read back data looks fine
+    let err = check_digest(Ok(vec![0; 4]), data, || { readbacks += 1; Ok(()) }).unwrap_err();
+    assert_eq!((err.kind(), readbacks), (ErrorKind::InvalidData, 1));
+    // Without checksums the data is read back instead
+    let unsupported = || Err(Error::from(ErrorKind::Unsupported));
+    assert!(check_digest(unsupported(), data, || { readbacks += 1; Ok(()) }).is_ok());
+    assert_eq!(readbacks, 2);
+    let failed = check_digest(unsupported(), data, || Err(Error::from(ErrorKind::InvalidData)));
+    assert_eq!(failed.unwrap_err().kind(), ErrorKind::InvalidData);
+    let err = check_digest(Err(Error::from(ErrorKind::TimedOut)), data, || { readbacks += 1; Ok(()) }).unwrap_err();
+    assert_eq!((err.kind(), readbacks), (ErrorKind::TimedOut, 2));
+}
+
+#[test]
+fn archive_round_trip() {
+    use archive::{Manifest, MemoryEntry};
+    use fmp::{MemFlags, MemInfo};
+    let flash = MemInfo { size: 1024, flags: MemFlags(0x02), name: String::from("Internal Flash") };
+    let eeprom = MemInfo { size: 600, flags: MemFlags(0x05), name: String::from("EEPROM") };
+    let images = vec![vec![0xa5u8; 1024], (0..600).map(|i| i as u8).collect::<Vec<u8>>()];
+    let mut manifest = Manifest::new(info::RadioInfo::parse("MD-UV3x0 sn=1234"));
+    assert_eq!(manifest.add(0, &flash, &images[0]), "mem0.bin");
+    manifest.add(1, &eeprom, &images[1]);
+    let text = manifest.to_string();
+    assert!(text.contains("[memory 1]\nname = EEPROM\nsize = 600\nflags = 0x05\n"));
+    let mut tar = Vec::new();
+    archive::write(&mut tar, &manifest, &images).unwrap();
+    assert!(archive::is_archive(&tar));
+    assert_eq!(tar.len() % 512, 0);
+    let backup = archive::read(&mut &tar[..]).unwrap();
+    assert_eq!(backup.manifest.radio.serial.as_deref(), Some("1234"));
+    assert_eq!(backup.manifest.created.timestamp(), manifest.created.timestamp());
+    assert_eq!(backup.manifest.memories, manifest.memories);
+    assert_eq!(backup.images, images);
+    assert!(matches!(backup.manifest.memories[1], MemoryEntry { index: 1, .. }));
+    // A single image is read from a file without the others
+    let path = std::env::temp_dir().join(format!("rtxlink-test-{}.tar", std::process::id()));
+    std::fs::write(&path, &tar).unwrap();
+    assert_eq!(archive::read_image(&path, &manifest.memories[1]).unwrap(), images[1]);
+    // Earlier backups are looked up by memory and digest
+    let (dir, digest) = (std::env::temp_dir(), &manifest.memories[1].sha256);
+    assert_eq!(flow::find_image(&dir, "rtxlink-test-", &eeprom, digest), Some(images[1].clone()));
+    assert_eq!(flow::find_image(&dir, "rtxlink-test-", &eeprom, &[0; 32]), None);
+    assert_eq!(flow::find_image(&dir, "rtxlink-test-", &flash, digest), None);
+    assert_eq!(flow::find_image(&dir, "other-", &eeprom, digest), None);
+    let mut other = manifest.memories[0].clone();
+    other.file = String::from("mem7.bin");
+    assert!(archive::read_image(&path, &other).is_err());
+    std::fs::remove_file(&path).unwrap();
+    // A corrupted image is detected through its digest
+    let image_start = tar.len() - 1024 - 512;
+    tar[image_start] ^= 0xff;
+    assert!(archive::read(&mut &tar[..]).is_err());
+    assert!(!archive::is_archive(&images[0]));
+    // A header claiming more data than the archive holds is not trusted
+    let mut huge = tar[..1024].to_vec();
+    huge[124..136].copy_from_slice(b"77777777777\0");
+    let sum: u32 = huge[..512].iter().enumerate()
+                              .map(|(i, &b)| if (148..156).contains(&i) { 32 } else { b as u32 })
+                              .sum();
+    huge[148..156].copy_from_slice(format!("{sum:06o}\0 ").as_bytes());
+    assert_eq!(archive::read(&mut &huge[..]).err().unwrap().kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(Manifest::parse("format = 99\ncreated = 2024-05-04T10:21:33+00:00\n").is_err());
+}
+
+#[test]
+fn flow_backup_destination() {
+    use std::io::ErrorKind;
+    // The destination is checked before talking to the radio
+    for dest in ["backups/radio", "https://example.com/backups"] {
+        let err = flow::backup(Some(String::from(dest)), None).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn flow_identity_mismatches() {
+    use flow::identity_mismatches;
+    use info::RadioInfo;
+    let radio = RadioInfo::parse("MD-UV3x0 fw=v0.3.6 sn=1234");
+    assert!(identity_mismatches(&radio, &RadioInfo::parse("MD-UV3x0 fw=v0.3.5 sn=1234")).is_empty());
+    // An unknown serial number is not a mismatch
+    assert!(identity_mismatches(&radio, &RadioInfo::parse("MD-UV3x0")).is_empty());
+    assert_eq!(identity_mismatches(&radio, &RadioInfo::parse("MD-UV3x0 sn=5678")).len(), 1);
+    assert_eq!(identity_mismatches(&radio, &RadioInfo::parse("GD-77 sn=5678")).len(), 2);
+}
+
+#[test]
+fn flow_plan_image() {
+    use flow::{plan_image, RestoreOptions};
+    use fmp::{MemFlags, MemInfo};
+    use info::RadioInfo;
+    let mem = |name: &str, size, flags| MemInfo { size, flags: MemFlags(flags), name: String::from(name) };
+    let radio = RadioInfo::parse("MD-UV3x0 fw=v0.3.6 sn=1234");
+    let (boot, flash, eeprom) = (mem("Bootloader", 256, 0x01), mem("Internal Flash", 1024, 0x02), mem("EEPROM", 512, 0x02));
+    let archive = |mems: &[(usize, &MemInfo)], origin: &str| {
+        let mut manifest = archive::Manifest::new(RadioInfo::parse(origin));
+        let images: Vec<Vec<u8>> = mems.iter().map(|(_, m)| vec![0x5a; m.size as usize]).collect();
+        for ((i, m), image) in mems.iter().zip(&images) {
+            manifest.add(*i, m, image);
+        }
+        let mut tar = Vec::new();
+        archive::write(&mut tar, &manifest, &images).unwrap();
+        tar
+    };
+    let same = archive(&[(0, &boot), (1, &flash), (2, &eeprom)], "MD-UV3x0 fw=v0.3.6 sn=1234");
+    let renamed = mem("Config", 512, 0x02);
+    let force = RestoreOptions { force: true, ..Default::default() };
+    // (radio memories, backup, memory index, forced, expected targets, expected warnings)
+    let cases = [
+        // Same index, the read-only bootloader is skipped
+        ([boot.clone(), flash.clone(), eeprom.clone()], &same, None, false, vec![1, 2], 0),
+        // Read-only memories are restored when asked for explicitly
+        ([boot.clone(), flash.clone(), eeprom.clone()], &same, Some(0), false, vec![0], 0),
+        ([boot.clone(), flash.clone(), eeprom.clone()], &same, Some(2), false, vec![2], 0),
+        // A memory moved to another index is restored there with a warning
+        ([eeprom.clone(), flash.clone(), boot.clone()], &same, None, false, vec![1, 0], 1),
+        // A memory only matching by size is a mismatch
+        ([boot.clone(), flash.clone(), renamed.clone()], &same, None, true, vec![1, 2], 1),
+    ];
+    for (mem_list, file, mem_index, forced, targets, warnings) in cases {
+        let options = if forced { force } else { RestoreOptions::default() };
+        let plan = plan_image(radio.clone(), &mem_list, mem_index, "backup.tar", file, &options).unwrap();
+        assert_eq!(plan.targets.iter().map(|t| t.mem_index).collect::<Vec<usize>>(), targets);
+        assert_eq!(plan.warnings.len(), warnings, "{:?}", plan.warnings);
+        assert!(plan.origin.is_some() && plan.backup.is_none());
+    }
+    // Mismatches are refused unless forced
+    let size_only = [boot.clone(), flash.clone(), renamed];
+    let err = plan_image(radio.clone(), &size_only, None, "backup.tar", &same, &RestoreOptions::default()).err().unwrap();
+    assert!(err.to_string().ends_with("use --force to restore anyway"));
+    let other = archive(&[(1, &flash)], "MD-UV3x0 sn=5678");
+    let mem_list = [boot, flash.clone(), eeprom];
+    assert!(plan_image(radio.clone(), &mem_list, None, "other.tar", &other, &RestoreOptions::default()).is_err());
+    assert!(plan_image(radio.clone(), &mem_list, None, "other.tar", &other, &force).is_ok());
+    assert!(plan_image(radio.clone(), &mem_list, Some(3), "backup.tar", &same, &force).is_err());
+    // A raw image always requires force, its legacy name is checked if any
+    let image = vec![0xffu8; 1024];
+    assert!(plan_image(radio.clone(), &mem_list, Some(1), "fw.bin", &image, &RestoreOptions::default()).is_err());
+    let plan = plan_image(radio.clone(), &mem_list, Some(1), "fw.bin", &image, &force).unwrap();
+    assert_eq!((plan.targets.len(), plan.warnings.len()), (1, 1));
+    let legacy = "MD-UV3x0 sn=1234_InternalFlash_1024_04052024.bin";
+    assert_eq!(flow::legacy_origin(legacy), Some((RadioInfo::parse("MD-UV3x0 sn=1234"), String::from("InternalFlash"), 1024)));
+    assert_eq!(plan_image(radio.clone(), &mem_list, Some(1), legacy, &image, &force).unwrap().warnings.len(), 1);
+    assert_eq!(plan_image(radio.clone(), &mem_list, Some(2), legacy, &image[..512], &force).unwrap().warnings.len(), 2);
+    let foreign = "GD-77_InternalFlash_1024_04052024.bin";
+    assert_eq!(plan_image(radio, &mem_list, Some(1), foreign, &image, &force).unwrap().warnings.len(), 2);
+    assert_eq!(flow::legacy_origin("fw.bin"), None);
+}
+
+#[test]
+fn shell_completions() {
+    use shell::{common_prefix, completions};
+    assert_eq!(completions("e"), vec!["echo", "exit"]);
+    assert_eq!(completions("get "), vec!["freqrx", "freqtx", "time", "gps", "info"]);
+    assert_eq!(completions("get freq"), vec!["freqrx", "freqtx"]);
+    assert_eq!(completions("set time "), vec!["sync"]);
+    assert!(completions("tune 145.6 ").is_empty());
+    assert!(completions("frobnicate ").is_empty());
+    assert_eq!(common_prefix(&["freqrx", "freqtx"]), "freq");
+    assert_eq!(common_prefix(&["echo", "exit"]), "e");
+    assert_eq!(common_prefix(&["gps"]), "gps");
+}
+
+#[test]
+fn monitor_sparkline_fit() {
+    use monitor::{fit, sparkline};
+    use std::collections::VecDeque;
+    let history: VecDeque<f32> = [1.0, 2.0, 3.0, 8.0].into_iter().collect();
+    assert_eq!(sparkline(&history, 10), "▁▂▃█");
+    // Only the most recent samples fit
+    assert_eq!(sparkline(&history, 2), "▁█");
+    assert_eq!(sparkline(&[5.0, 5.0].into_iter().collect(), 10), "▁▁");
+    assert_eq!(sparkline(&VecDeque::new(), 10), "");
+    assert_eq!(fit("RSSI", 6), "RSSI  ");
+    assert_eq!(fit("Temperature", 4), "Temp");
+    assert_eq!(fit("°C", 3), "°C ");
+}
+
+#[test]
+fn dat_send_frames() {
+    use dat::{frames, DAT_PAYLOAD_SIZE};
+    // Empty data still takes one frame
+    assert_eq!(frames(&[]), vec![vec![1, 254]]);
+    assert_eq!(frames(&[0xaa, 0xbb]), vec![vec![1, 254, 0xaa, 0xbb]]);
+    // No data is lost at frame boundaries and no empty frame follows full ones
+    let data: Vec<u8> = (0..2 * DAT_PAYLOAD_SIZE + 3).map(|i| i as u8).collect();
+    let sent = frames(&data);
+    assert_eq!(sent.iter().map(|f| f.len()).collect::<Vec<_>>(), vec![1024, 1024, 5]);
+    assert_eq!(sent.iter().flat_map(|f| f[2..].to_vec()).collect::<Vec<u8>>(), data);
+    assert_eq!(frames(&data[..DAT_PAYLOAD_SIZE]).len(), 1);
+    // Block numbers wrap around after 255
+    let sent = frames(&vec![0; 256 * DAT_PAYLOAD_SIZE]);
+    assert_eq!(sent[254][..2], [255, 0]);
+    assert_eq!(sent[255][..2], [0, 255]);
+}
+
+#[test]
+fn dat_receive_block_numbers() {
+    use dat::block_data;
+    use std::io::ErrorKind;
+    assert_eq!(block_data(&[0, 255, 1, 2, 3], 0).unwrap(), &[1, 2, 3]);
+    assert!(block_data(&[7, 248], 7).unwrap().is_empty());
+    // Truncated frames, bad inverse and out of sequence blocks
+    assert_eq!(block_data(&[], 0).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(block_data(&[0], 0).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(block_data(&[0, 254, 1], 0).is_err());
+    assert!(block_data(&[2, 253, 1], 1).is_err());
+    // Block numbers wrap around after 255
+    let mut block_number: u8 = 0;
+    for n in 0..600usize {
+        let frame = [n as u8, 255 - n as u8, 0x42];
+        assert_eq!(block_data(&frame, block_number).unwrap(), &[0x42]);
+        block_number = block_number.wrapping_add(1);
+    }
+    assert!(block_data(&[0, 255], 1).is_err());
+}
+
+#[test]
+fn dat_receive_sizes() {
+    use dat::{frames, Reception};
+    // Empty data takes one frame without data, as sent by frames
+    let mut reception = Reception::new(0);
+    assert!(!reception.complete());
+    assert!(reception.accept(&[0, 255]).unwrap().is_empty());
+    assert!(reception.complete());
+    let sent = frames(&[]);
+    assert_eq!((sent.len(), sent[0].len()), (1, 2));
+    // Data past the expected size is refused instead of written
+    let mut reception = Reception::new(3);
+    assert_eq!(reception.accept(&[0, 255, 1, 2]).unwrap(), &[1, 2]);
+    assert!(!reception.complete());
+    assert_eq!(reception.accept(&[1, 254, 3, 4]).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(reception.accept(&[1, 254, 3]).unwrap(), &[3]);
+    assert!(reception.complete());
+    assert!(Reception::new(0).accept(&[0, 255, 1]).is_err());
+}
+
+#[test]
+fn sync_local_tree() {
+    use std::fs;
+    let root = std::env::temp_dir().join(format!("rtxlink-test-sync-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    // A missing or non directory source is an error, not an empty tree
+    assert_eq!(sync::local_tree(&root).unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    fs::create_dir_all(root.join("prompts")).unwrap();
+    fs::write(root.join("a.txt"), "abc").unwrap();
+    fs::write(root.join("prompts/1.wav"), "12345").unwrap();
+    assert!(sync::local_tree(&root.join("a.txt")).is_err());
+    // Symbolic links are skipped, even when they loop
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&root, root.join("prompts/loop")).unwrap();
+    let tree = sync::local_tree(&root).unwrap();
+    let entries: Vec<(&str, bool, u64)> = tree.iter().map(|(p, e)| (p.as_str(), e.is_dir, e.size)).collect();
+    assert_eq!(entries, vec![("a.txt", false, 3), ("prompts", true, 0), ("prompts/1.wav", false, 5)]);
+    fs::remove_dir_all(&root).unwrap();
+}